@@ -0,0 +1,25 @@
+// load arbitrary bytes as a program and run it for a while:
+// whatever the program does, the emulator must return an error instead of panicking
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust_chip8::chip8::Chip8;
+
+// enough for a few seconds of a program at the default speed
+const STEPS: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    let mut chip8 = Chip8::new();
+    if chip8.load_bytes(data).is_err() {
+        return;
+    }
+
+    for i in 0..STEPS {
+        // errors are expected from random bytes, and only stop the program
+        if chip8.step().is_err() {
+            break;
+        }
+        if i % 10 == 9 {
+            chip8.tick_timers();
+        }
+    }
+});
@@ -0,0 +1,92 @@
+// export of the chip8 screen as a monochrome (1 bit per pixel) BMP image; see
+// https://en.wikipedia.org/wiki/BMP_file_format
+
+use std::fs::write;
+
+// size of the file header and of the BITMAPINFOHEADER
+const FILE_HEADER_SIZE: u32 = 14;
+const INFO_HEADER_SIZE: u32 = 40;
+
+// black and white, stored as blue, green, red and an unused byte
+const PALETTE: [u8; 8] = [0, 0, 0, 0, 255, 255, 255, 0];
+
+/// encode a display (indexed as display[x][y]) as a 1 bit per pixel BMP image
+pub fn encode(display: &[Vec<u8>]) -> Vec<u8> {
+    let width = display.len();
+    let height = display.first().map_or(0, |column| column.len());
+
+    // rows of pixels are padded to a multiple of 4 bytes
+    let row_size = width.div_ceil(32) * 4;
+
+    let data_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + PALETTE.len() as u32;
+    let data_size = (row_size * height) as u32;
+
+    let mut bmp = Vec::with_capacity((data_offset + data_size) as usize);
+
+    // file header
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&(data_offset + data_size).to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes()); // reserved
+    bmp.extend_from_slice(&data_offset.to_le_bytes());
+
+    // BITMAPINFOHEADER
+    bmp.extend_from_slice(&INFO_HEADER_SIZE.to_le_bytes());
+    bmp.extend_from_slice(&(width as i32).to_le_bytes());
+    bmp.extend_from_slice(&(height as i32).to_le_bytes()); // positive: the rows are stored bottom-up
+    bmp.extend_from_slice(&1u16.to_le_bytes()); // color planes
+    bmp.extend_from_slice(&1u16.to_le_bytes()); // bits per pixel
+    bmp.extend_from_slice(&0u32.to_le_bytes()); // no compression
+    bmp.extend_from_slice(&data_size.to_le_bytes());
+    bmp.extend_from_slice(&2835i32.to_le_bytes()); // horizontal resolution: 72 DPI
+    bmp.extend_from_slice(&2835i32.to_le_bytes()); // vertical resolution: 72 DPI
+    bmp.extend_from_slice(&2u32.to_le_bytes()); // colors in the palette
+    bmp.extend_from_slice(&0u32.to_le_bytes()); // all colors are important
+
+    bmp.extend_from_slice(&PALETTE);
+
+    // pixels, starting from the bottom row, the leftmost pixel being the most significant bit
+    for y in (0..height).rev() {
+        let mut row = vec![0u8; row_size];
+        for (x, column) in display.iter().enumerate() {
+            if column[y] == 1 {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        bmp.extend_from_slice(&row);
+    }
+
+    bmp
+}
+
+/// write a display (indexed as display[x][y]) to a 1 bit per pixel BMP file
+pub fn save(file_path: &str, display: &[Vec<u8>]) -> Result<(), String> {
+    write(file_path, encode(display)).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{HIGH_RES_XPX, HIGH_RES_YPX, XPX, YPX};
+
+    #[test]
+    fn encode_display() {
+        let mut display = vec![vec![0; YPX]; XPX];
+        display[0][0] = 1;
+        display[9][YPX - 1] = 1;
+
+        let bmp = encode(&display);
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(bmp.len(), 62 + 8 * YPX);
+        assert_eq!(&bmp[2..6], &(bmp.len() as u32).to_le_bytes());
+        assert_eq!(&bmp[10..14], &62u32.to_le_bytes());
+
+        // the first stored row is the bottom one
+        assert_eq!(&bmp[62..70], &[0, 0x40, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&bmp[bmp.len() - 8..], &[0x80, 0, 0, 0, 0, 0, 0, 0]);
+
+        // the high resolution screen has rows twice as long
+        let bmp = encode(&vec![vec![0; HIGH_RES_YPX]; HIGH_RES_XPX]);
+        assert_eq!(bmp.len(), 62 + 16 * HIGH_RES_YPX);
+        assert_eq!(&bmp[18..22], &128i32.to_le_bytes());
+    }
+}
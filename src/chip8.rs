@@ -1,30 +1,85 @@
-extern crate log;
-
-use log::{debug, error, trace, warn};
+use flate2::read::GzDecoder;
+use log::{debug, info, log_enabled, trace, warn, Level};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::fs::read;
+use std::io::Read;
+use std::time::{Duration, Instant};
 
 // size of the screen, in pixels
-const XPX: usize = 64;
-const YPX: usize = 32;
+pub const XPX: usize = 64;
+pub const YPX: usize = 32;
+// size of the SUPER-CHIP high resolution screen, in pixels
+pub const HIGH_RES_XPX: usize = 128;
+pub const HIGH_RES_YPX: usize = 64;
+
+// number of HP48 RPL user flags the SUPER-CHIP can save registers to
+pub const RPL_SIZE: usize = 8;
 
 // size of the internal memory (4K)
 const MEM_SIZE: usize = 4096;
 
 // memory reserved to store the fontset
-const FONTSET_SIZE: usize = 80;
+pub const FONTSET_SIZE: usize = 80;
 
-// memory reserved for the display functions
-const DISPLAY_SIZE: usize = 256;
+// address of memory where the fontset starts
+const FONT_BASE: usize = 0;
 
-// memory reserved for the stack
-const STACK_SIZE: usize = 96;
-
-const REGISTER_NUM: usize = 16;
-const KEY_NUM: usize = 16;
+pub const REGISTER_NUM: usize = 16;
+pub const KEY_NUM: usize = 16;
 const STACK_LAYERS: usize = 16;
 
+// maximum number of instructions run when stepping over or out of a subroutine,
+// in case it never returns
+const MAX_STEP_CYCLES: usize = 1_000_000;
+
+// default instructions run per second by advance(), like the frontend at normal speed
+const CLOCK_HZ: u32 = 60;
+
+// fastest clock of advance(), which runs one instruction per nanosecond at most
+const MAX_CLOCK_HZ: u32 = 1_000_000_000;
+
+// frequency at which the timers are decremented
+const TIMER_HZ: u32 = 60;
+
+// number of opcode families, identified by the first nibble of the opcode
+const OPCODE_FAMILIES: usize = 16;
+
 // adress of memory where the program counter start
-const PC_START: usize = 512; // 512 == 0x200
+pub const PC_START: usize = 512; // 512 == 0x200
+
+// first opcode of a HIRES CHIP-8 (64x64) program: it jumps over the
+// bundled interpreter patch, which later gets called with 0x0230 to clear the screen
+const HIRES_STARTUP_OPCODE: u16 = 0x1260;
+const HIRES_CLEAR_OPCODE: u16 = 0x0230;
+
+// parameters of the 64 bit FNV-1a hash
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01B3;
+
+// first bytes of a save state, followed by the version of its layout
+const STATE_MAGIC: [u8; 4] = *b"C8ST";
+const STATE_VERSION: u8 = 2;
+
+// size of a save state: header, memory, registers, program counter, index register,
+// resolution, display, timers, stack, stack pointer, keys, key wait and program size
+const STATE_SIZE: usize = STATE_MAGIC.len()
+    + 1
+    + MEM_SIZE
+    + REGISTER_NUM
+    + 2
+    + 2
+    + 1
+    + 16 * HIGH_RES_YPX
+    + 2
+    + 2 * STACK_LAYERS
+    + 1
+    + KEY_NUM
+    + 2
+    + 2;
+
+// first bytes of a gzip file
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
 
 const CHIP8_FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -45,6 +100,231 @@ const CHIP8_FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// behaviors which differ between chip8 interpreters, and which programs rely on.
+/// The default is the behavior this emulator always had, which matches neither preset
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    /// DXY0 draws a 8x16 sprite. Otherwise, a sprite with a height of 0 draws nothing
+    pub draw_zero_height: bool,
+    /// 8XY6 and 8XYE shift VY into VX (COSMAC VIP). Otherwise, VX is shifted in place
+    pub shift_uses_vy: bool,
+    /// FX55 and FX65 leave I pointing after the last register (COSMAC VIP).
+    /// Otherwise, I doesn't change
+    pub increment_index_on_load_store: bool,
+    /// sprites going past an edge of the screen wrap around to the other side.
+    /// Otherwise, they are clipped
+    pub wrap_sprites: bool,
+    /// 8XY1, 8XY2 and 8XY3 set VF to 0 (COSMAC VIP). Otherwise, VF is left untouched
+    pub vf_reset_on_logic: bool,
+    /// 00FE and 00FF keep the display when switching resolution.
+    /// Otherwise, they clear it (SUPER-CHIP 1.1)
+    pub keep_display_on_resolution_switch: bool,
+}
+
+/// the quirks of well-known interpreters, which can be picked by name
+pub const QUIRKS_PRESETS: &[(&str, Quirks)] = &[
+    // the original interpreter of the COSMAC VIP
+    (
+        "chip8",
+        Quirks {
+            draw_zero_height: false,
+            shift_uses_vy: true,
+            increment_index_on_load_store: true,
+            wrap_sprites: false,
+            vf_reset_on_logic: true,
+            keep_display_on_resolution_switch: false,
+        },
+    ),
+    // SUPER-CHIP 1.1 on the HP48
+    (
+        "schip",
+        Quirks {
+            draw_zero_height: true,
+            shift_uses_vy: false,
+            increment_index_on_load_store: false,
+            wrap_sprites: false,
+            vf_reset_on_logic: false,
+            keep_display_on_resolution_switch: false,
+        },
+    ),
+];
+
+impl Quirks {
+    /// get the quirks of a preset from its name
+    pub fn preset(name: &str) -> Option<Quirks> {
+        QUIRKS_PRESETS
+            .iter()
+            .find(|(preset_name, _)| *preset_name == name)
+            .map(|&(_, quirks)| quirks)
+    }
+}
+
+/// what went wrong while loading or running a program
+#[derive(Clone, Debug, PartialEq)]
+pub enum Chip8Error {
+    /// an opcode the emulator can't run
+    UnknownOpcode(u16),
+    /// a subroutine called with every layer of the stack in use
+    StackOverflow,
+    /// a return without any subroutine to exit, when strict
+    StackUnderflow,
+    /// an access going past the end of the memory, from the given address
+    OutOfBounds(usize),
+    /// a write to the fontset or reserved memory, when it is protected
+    ProtectedWrite(usize),
+    /// a jump to itself at the given address, when trapping halts
+    Halt(usize),
+    /// a program too large for the memory
+    RomTooLarge,
+    /// the program file couldn't be read or decompressed
+    Io(String),
+    /// data too large for the memory, of the given size
+    MemoryTooLarge(usize),
+    /// a save state which can't be restored, for the given reason
+    InvalidState(String),
+    /// a step out of the subroutine while not inside one
+    NotInSubroutine,
+    /// the program waits for a key at the given address, so stepping can't go on
+    WaitingForKey(usize),
+    /// stepping gave up after the given number of instructions
+    StepLimit(usize),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode(opcode) => write!(f, "unknown opcode {:04X} !", opcode),
+            Chip8Error::StackOverflow => write!(
+                f,
+                "stack overflow: recursion of more than {} subroutines !",
+                STACK_LAYERS
+            ),
+            Chip8Error::StackUnderflow => write!(f, "stack underflow: no subroutine to exit !"),
+            Chip8Error::OutOfBounds(address) => write!(
+                f,
+                "attempt to access out of memory from address {:X} !",
+                address
+            ),
+            Chip8Error::ProtectedWrite(address) => {
+                write!(f, "attempt to write to reserved address {:X} !", address)
+            }
+            Chip8Error::Halt(address) => write!(
+                f,
+                "halt: the program jumps to itself at address {:X} !",
+                address
+            ),
+            Chip8Error::RomTooLarge => {
+                write!(f, "the program doesn't fit in the emulator's memory !")
+            }
+            Chip8Error::Io(err) => write!(f, "unable to read the program: {} !", err),
+            Chip8Error::MemoryTooLarge(size) => write!(
+                f,
+                "{} bytes don't fit in the emulator's {} bytes of memory !",
+                size, MEM_SIZE
+            ),
+            Chip8Error::InvalidState(reason) => write!(f, "invalid save state: {} !", reason),
+            Chip8Error::NotInSubroutine => write!(f, "not inside a subroutine !"),
+            Chip8Error::WaitingForKey(address) => {
+                write!(f, "the program waits for a key at address {:X} !", address)
+            }
+            Chip8Error::StepLimit(cycles) => write!(
+                f,
+                "the program didn't get there after {} instructions !",
+                cycles
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+/// what happened while running a program, to find why it doesn't work
+#[derive(Debug, Default, PartialEq)]
+pub struct CompatReport {
+    /// number of instructions run
+    pub cycles: usize,
+    /// opcodes the emulator can't run, with the number of times they were met
+    pub unknown_opcodes: BTreeMap<u16, usize>,
+    /// errors raised by the instructions (out of bounds accesses, stack anomalies ...),
+    /// with the number of times they were raised
+    pub errors: BTreeMap<String, usize>,
+    /// number of writes of the program to its own code
+    pub self_modifying_writes: usize,
+    /// address of the jump to itself which ended the program, if any
+    pub halted_at: Option<usize>,
+    /// whether the program was stopped waiting for a key
+    pub waiting_for_key: bool,
+}
+
+/// a copy of the machine state, see Chip8::debug_state()
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DebugState {
+    /// V0 to VF
+    pub registers: [u8; REGISTER_NUM],
+    /// address of the next instruction
+    pub program_counter: usize,
+    /// the I register
+    pub index_register: u16,
+    /// return addresses of the subroutines, the first stack_pointer ones being used
+    pub stack: [u16; STACK_LAYERS],
+    pub stack_pointer: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// 1 for each pressed key, 0 otherwise
+    pub keys: [u8; KEY_NUM],
+}
+
+// register dump, e.g.
+//   PC=204 I=2EA SP=1 DT=00 ST=00
+//   V0=05 V1=07 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00
+//   V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00
+//   stack: 202
+//   keys: 5 A
+impl fmt::Display for DebugState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "PC={:03X} I={:03X} SP={:X} DT={:02X} ST={:02X}",
+            self.program_counter,
+            self.index_register,
+            self.stack_pointer,
+            self.delay_timer,
+            self.sound_timer
+        )?;
+
+        for (row, values) in self.registers.chunks(8).enumerate() {
+            let registers: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("V{:X}={:02X}", 8 * row + i, value))
+                .collect();
+            writeln!(f, "{}", registers.join(" "))?;
+        }
+
+        let stack: Vec<String> = self.stack[..self.stack_pointer.min(STACK_LAYERS)]
+            .iter()
+            .map(|address| format!("{:03X}", address))
+            .collect();
+        writeln!(f, "stack: {}", stack.join(" "))?;
+
+        let keys: Vec<String> = (0..KEY_NUM)
+            .filter(|&key| self.keys[key] != 0)
+            .map(|key| format!("{:X}", key))
+            .collect();
+        write!(f, "keys: {}", keys.join(" "))
+    }
+}
+
+// what VF holds, to notice a flag overwriting a value of the program
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FlagRegister {
+    Unknown,
+    // set by the program, as a general register
+    Value,
+    // a value of the program overwritten by the flag of the opcode at this address
+    Clobbered(usize, u16),
+}
+
 pub struct Chip8 {
     // memory of the chip8 system
     memory: [u8; MEM_SIZE],
@@ -58,9 +338,20 @@ pub struct Chip8 {
     // index register
     index_register: u16,
 
-    // representation of the screen: 64*32
-    // the screen is black and white, so the value taken can either be 0 or 1;
-    display: [[u8; YPX]; XPX], // called later as display[x][y]
+    // representation of the screen: 64*32, 128*64 in high resolution or 64*64 for the
+    // HIRES CHIP-8 programs.
+    // the screen is black and white, so each pixel is packed as a single bit:
+    // one u128 per row, the most significant bit being the leftmost pixel.
+    // In low resolution, only the top left 64*32 pixels are used
+    display: [u128; HIGH_RES_YPX], // called later as display[y]
+    // pixels turned on by the last drawing operation, packed the same way
+    drawn: [u128; HIGH_RES_YPX],
+    // whether the SUPER-CHIP high resolution mode is on
+    high_res: bool,
+    // whether the loaded program is a HIRES CHIP-8 one, with a 64*64 screen
+    hires: bool,
+    // whether the screen changed since the last take_dirty()
+    dirty: bool,
 
     // timers, decrementing every 1/60 second
     delay_timer: u8, // used for game animations & timing
@@ -68,7 +359,7 @@ pub struct Chip8 {
 
     // the stack, used to store the program counter after a subroutine call
     stack: [u16; STACK_LAYERS], // the stack has 16 levels
-    stack_pointer: usize,       // "pointer" to track the current stack level
+    stack_pointer: usize,       // number of addresses on the stack, i.e. the next free level
 
     // hex keycodes for the chip8 keyboard, which has 16 keys
     key: [u8; KEY_NUM],
@@ -77,6 +368,72 @@ pub struct Chip8 {
     wait_for_key: bool,
     // this holds a reference to the register which will contain the pressed key
     wait_for_key_register: usize,
+
+    // size of the loaded program, in bytes
+    rom_len: usize,
+
+    // the HP48 RPL user flags, where a SUPER-CHIP program can save registers (e.g. high
+    // scores). They outlive the program, so they survive a reset
+    rpl: [u8; RPL_SIZE],
+
+    // the hexadecimal font, kept to restore it on reset
+    font: [u8; FONTSET_SIZE],
+
+    // number of writes to the loaded program, by the program itself
+    self_modifying_writes: usize,
+
+    // compatibility behaviors
+    quirks: Quirks,
+
+    // whether every conditional skip should be logged
+    trace_branches: bool,
+
+    // whether to warn when VF looks used as a general register and as a flag
+    warn_vf_reuse: bool,
+    vf: FlagRegister,
+
+    // whether the program misbehaving is an error, instead of a warning
+    strict: bool,
+
+    // whether a jump to itself is an error, instead of the usual way to stop a program
+    trap_halt: bool,
+
+    // whether unknown opcodes are skipped with a warning, instead of being an error
+    ignore_unknown: bool,
+
+    // whether the program is forbidden to write below PC_START (fontset and reserved memory)
+    protect_reserved: bool,
+
+    // addresses run_until_break() stops at
+    breakpoints: HashSet<usize>,
+
+    // values of the registers locked by the user (e.g. for cheats), re-applied after each step
+    frozen_register: [Option<u8>; REGISTER_NUM],
+
+    // number of cycles a draw (DXYN) takes
+    draw_cost: u32,
+    // cycles left before the next instruction runs, after a costly draw
+    stall: u32,
+
+    // seed of the random number generator, see seed()
+    seed: u64,
+    // state of the xorshift64* random number generator used by CXNN, never 0
+    rng: u64,
+
+    // hash of the instructions run, see trace_hash()
+    trace_hash: u64,
+
+    // instructions run per second by advance()
+    clock_hz: u32,
+    // time given to advance() not yet spent running an instruction
+    pending_time: Duration,
+    // instructions run by advance() since the timers were last decremented, counted
+    // TIMER_HZ at a time so that the timers tick exactly every clock_hz of them
+    timer_phase: u32,
+
+    // number of executions and total time spent per opcode family.
+    // None unless profiling has been enabled, so normal runs don't pay for it
+    profile: Option<[(u64, Duration); OPCODE_FAMILIES]>,
 }
 
 impl Chip8 {
@@ -88,7 +445,12 @@ impl Chip8 {
             // first byte of the program
             program_counter: PC_START,
             index_register: 0,
-            display: [[0; YPX]; XPX],
+            display: [0; HIGH_RES_YPX],
+            drawn: [0; HIGH_RES_YPX],
+            high_res: false,
+            hires: false,
+            // nothing got rendered yet
+            dirty: true,
             delay_timer: 0,
             sound_timer: 0,
             stack: [0; STACK_LAYERS],
@@ -96,573 +458,3560 @@ impl Chip8 {
             key: [0; KEY_NUM],
             wait_for_key: false,
             wait_for_key_register: 0,
+            rom_len: 0,
+            rpl: [0; RPL_SIZE],
+            font: CHIP8_FONTSET,
+            self_modifying_writes: 0,
+            quirks: Quirks::default(),
+            trace_branches: false,
+            warn_vf_reuse: false,
+            vf: FlagRegister::Unknown,
+            strict: false,
+            ignore_unknown: false,
+            trap_halt: false,
+            protect_reserved: false,
+            breakpoints: HashSet::new(),
+            frozen_register: [None; REGISTER_NUM],
+            draw_cost: 1,
+            stall: 0,
+            seed: 1,
+            rng: 1,
+            trace_hash: FNV_OFFSET,
+            clock_hz: CLOCK_HZ,
+            pending_time: Duration::new(0, 0),
+            timer_phase: 0,
+            profile: None,
         };
+        chip8.set_seed(clock_seed());
 
         // load the fontset into the emulator memory
-        for i in 0..FONTSET_SIZE {
-            chip8.memory[i] = CHIP8_FONTSET[i];
-        }
+        chip8.set_fontset(CHIP8_FONTSET);
 
         chip8
     }
 
-    /// get the virtual screen
-    pub fn display(&self) -> &[[u8; YPX]; XPX] {
-        &self.display
+    /// get the virtual screen, unpacked as display[x][y] with a value of 0 or 1 per pixel.
+    /// It is width() pixels wide and height() pixels high, which changes with the resolution
+    pub fn display(&self) -> Vec<Vec<u8>> {
+        unpack(&self.display, self.width(), self.height())
     }
 
-    /// load the game into the emulator
-    pub fn load(&mut self, file_path: &str) -> Result<(), String> {
-        let binary_file = read(file_path).map_err(|err| err.to_string())?;
+    /// get the pixels turned on by the last drawing operation, laid out like display().
+    /// They are forgotten once taken, so that they're only reported once
+    pub fn take_drawn(&mut self) -> Vec<Vec<u8>> {
+        let drawn = unpack(&self.drawn, self.width(), self.height());
+        self.drawn = [0; HIGH_RES_YPX];
+        drawn
+    }
 
-        // check if the program fits in the emulator's memory
-        if binary_file.len() > MEM_SIZE - (FONTSET_SIZE + DISPLAY_SIZE + STACK_SIZE) {
-            return Err("The program doesn't fit in the emulator's memory !".to_string());
-        }
+    /// get the screen as RGBA bytes, row after row from the top left corner, with white
+    /// pixels on a black background, e.g. for an HTML canvas
+    pub fn display_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(4 * self.width() * self.height());
 
-        for (i, &byte) in binary_file.iter().enumerate() {
-            self.memory[PC_START + i] = byte;
+        for &row in self.display[..self.height()].iter() {
+            for x in 0..self.width() {
+                let px = if row >> (HIGH_RES_XPX - 1 - x) & 1 == 1 {
+                    255
+                } else {
+                    0
+                };
+                rgba.extend_from_slice(&[px, px, px, 255]);
+            }
         }
-        Ok(())
+
+        rgba
     }
 
-    /// reset all key states to unpressed
-    pub fn clear_keys(&mut self) {
-        for key in self.key.iter_mut() {
-            *key = 0;
+    /// whether the screen changed since the last call, e.g. to only render it when needed.
+    /// It starts out changed, so that the first frame gets rendered
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    /// width of the screen in pixels: 64, or 128 in high resolution
+    pub fn width(&self) -> usize {
+        if self.high_res {
+            HIGH_RES_XPX
+        } else {
+            XPX
         }
     }
 
-    /// mark a key as pressed
-    pub fn register_key(&mut self, key: u8) {
-        self.key[key as usize] = 1;
+    /// height of the screen in pixels: 32, or 64 in high resolution and for the
+    /// HIRES CHIP-8 programs
+    pub fn height(&self) -> usize {
+        if self.high_res || self.hires {
+            HIGH_RES_YPX
+        } else {
+            YPX
+        }
     }
 
-    /// emulate one step of the chip8
-    pub fn emulate(&mut self) -> Result<(), String> {
-        // get the opcode, which corresponds to a processor instruction. see:
-        // https://en.wikipedia.org/wiki/CHIP-8
-        // for an exhaustive list.
+    // the bits of a display row which are on the screen
+    fn row_mask(&self) -> u128 {
+        !0 << (HIGH_RES_XPX - self.width())
+    }
 
-        // we might need to stop the program until a certain key is pressed
+    /// get the virtual screen as text, one line per row: '#' for a set pixel, '.' otherwise
+    pub fn display_ascii(&self) -> String {
+        let mut ascii = String::with_capacity((self.width() + 1) * self.height());
 
-        if self.wait_for_key {
-            for (keycode, &key_state) in self.key.iter().enumerate() {
-                // a key is pressed
-                if key_state == 1 {
-                    self.wait_for_key = false;
-                    debug!("got key {}", keycode);
-                    self.register[self.wait_for_key_register] = keycode as u8;
-                    break;
-                }
+        for &row in self.display[..self.height()].iter() {
+            for x in 0..self.width() {
+                ascii.push(if row >> (HIGH_RES_XPX - 1 - x) & 1 == 1 {
+                    '#'
+                } else {
+                    '.'
+                });
             }
+            ascii.push('\n');
+        }
 
-            // still no key pressed, return from the function
-            if self.wait_for_key {
-                return Ok(());
+        ascii
+    }
+
+    /// start timing each executed opcode family
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some([(0, Duration::new(0, 0)); OPCODE_FAMILIES]);
+    }
+
+    /// get the number of executions and the total time spent in each opcode family,
+    /// indexed by the first nibble of the opcode
+    pub fn profile(&self) -> Option<&[(u64, Duration); OPCODE_FAMILIES]> {
+        self.profile.as_ref()
+    }
+
+    /// load the game into the emulator. Gzip compressed games are decompressed first
+    pub fn load(&mut self, file_path: &str) -> Result<(), Chip8Error> {
+        let mut binary_file = read(file_path).map_err(|err| Chip8Error::Io(err.to_string()))?;
+
+        let gz_extension = file_path.ends_with(".gz");
+        if gz_extension || binary_file.starts_with(&GZIP_MAGIC) {
+            let mut decompressed = Vec::new();
+            match GzDecoder::new(&binary_file[..]).read_to_end(&mut decompressed) {
+                Ok(_) => {
+                    debug!(
+                        "decompressed the program from {} to {} bytes",
+                        binary_file.len(),
+                        decompressed.len()
+                    );
+                    binary_file = decompressed;
+                }
+                Err(err) if gz_extension => return Err(Chip8Error::Io(err.to_string())),
+                // a program can start with the gzip magic bytes by chance
+                Err(_) => debug!("the program isn't gzip compressed, loading it as is"),
             }
         }
 
-        // opcodes are 2 bytes long.
-        // get the first byte, shift by a byte, combine with the second byte.
-        let opcode: u16 = (self.memory[self.program_counter] as u16) << 8
-            | (self.memory[self.program_counter + 1] as u16);
+        self.load_bytes(&binary_file)
+    }
 
-        debug!(
-            "----------- chip8 cycle: got opcode {:X} -----------",
-            opcode
-        );
+    /// load a game already in memory into the emulator, e.g. one embedded in the executable
+    pub fn load_bytes(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        // check if the program fits in the emulator's memory, from where it starts
+        if rom.len() > MEM_SIZE - PC_START {
+            return Err(Chip8Error::RomTooLarge);
+        }
 
-        // increase the program counter for the next opcode
-        self.program_counter += 2;
+        self.memory[PC_START..PC_START + rom.len()].copy_from_slice(rom);
+        self.rom_len = rom.len();
 
-        // decrement both timers
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        }
-        if self.sound_timer > 0 {
-            self.sound_timer -= 1;
+        // the HIRES CHIP-8 programs are recognized by their first opcode, and run
+        // on a 64x64 screen
+        self.hires = rom.len() >= 2 && (rom[0] as u16) << 8 | rom[1] as u16 == HIRES_STARTUP_OPCODE;
+        if self.hires {
+            info!("HIRES CHIP-8 program detected, switching to a 64x64 screen");
         }
 
-        // process our opcode here
-        match opcode & 0xF000 {
-            // multiple functions exist here, so we need another match
-            0x0000 => {
-                match opcode & 0xFFFF {
-                    // return from a subroutine
-                    0x00EE => {
-                        // jump back to the right address
-                        self.program_counter = self.stack[self.stack_pointer] as usize;
+        Ok(())
+    }
 
-                        if self.stack_pointer != 0 {
-                            self.stack_pointer -= 1;
-                            debug!("exiting subroutine.");
-                        } else {
-                            error!("no subroutine to exit !");
-                        }
-                    }
+    /// find the opcodes of the loaded program that the emulator can't run, with their address.
+    /// Every 2 bytes are decoded as an opcode, so data may be reported as well
+    pub fn unimplemented_opcodes(&self) -> Vec<(usize, u16)> {
+        (PC_START..PC_START + self.rom_len - self.rom_len % 2)
+            .step_by(2)
+            .map(|address| {
+                let opcode = (self.memory[address] as u16) << 8 | self.memory[address + 1] as u16;
+                (address, opcode)
+            })
+            // the clear of the HIRES CHIP-8 programs only exists for them
+            .filter(|&(_, opcode)| {
+                !is_implemented(opcode) && (!self.hires || opcode != HIRES_CLEAR_OPCODE)
+            })
+            .collect()
+    }
 
-                    // clear the display
-                    0x00E0 => {
-                        self.display = [[0; YPX]; XPX];
-                        debug!("cleared display.");
-                    }
+    /// run the program for at most `cycles` instructions, and gather everything abnormal.
+    /// Stops early if the program halts on a jump to itself or waits for a key.
+    /// The timers are decremented after each instruction, as at normal speed
+    pub fn compat_report(&mut self, cycles: usize) -> CompatReport {
+        let mut report = CompatReport::default();
+
+        // report the misbehaviors which are only warnings otherwise
+        let strict = self.strict;
+        self.strict = true;
+
+        while report.cycles < cycles {
+            if self.wait_for_key {
+                report.waiting_for_key = true;
+                break;
+            }
 
-                    _ => warn!("warning: ran into unknown opcode: {:X}", opcode),
+            let address = self.program_counter;
+            // an opcode out of memory makes emulate() fail right after
+            if let Ok(opcode) = self.fetch() {
+                if !is_implemented(opcode) {
+                    *report.unknown_opcodes.entry(opcode).or_insert(0) += 1;
                 }
             }
 
-            // jump
-            0x1000 => {
-                let jump_address = opcode & 0x0FFF;
-                self.program_counter = jump_address as usize;
-                debug!("jumping to address {}", jump_address);
-                trace!(
-                    "corresponding address in the .ch8 file: {}",
-                    jump_address - PC_START as u16
-                );
+            // the unknown opcodes are already counted on their own
+            match self.emulate() {
+                Ok(()) | Err(Chip8Error::UnknownOpcode(_)) => {}
+                Err(e) => *report.errors.entry(e.to_string()).or_insert(0) += 1,
             }
+            self.tick_timers();
+            report.cycles += 1;
 
-            // call a subroutine
-            0x2000 => {
-                // where is the subroutine to call
-                let subroutine_address = opcode & 0x0FFF;
-                self.stack_pointer += 1;
+            // nothing can happen anymore
+            if self.program_counter == address && !self.wait_for_key && self.stall == 0 {
+                report.halted_at = Some(address);
+                break;
+            }
+        }
 
-                if self.stack_pointer == 16 {
-                    error!("recursion of more than 16 routines !");
-                }
+        self.strict = strict;
+        report.self_modifying_writes = self.self_modifying_writes;
+        report
+    }
 
-                // store on the stack where to return after the subroutine exited
-                self.stack[self.stack_pointer] = self.program_counter as u16;
+    /// run `cycles` instructions without any frontend, e.g. to test a program.
+    /// The timers tick after each instruction, as with advance() at 60Hz. Stops at the first error
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<(), Chip8Error> {
+        for _ in 0..cycles {
+            self.emulate()?;
+            self.tick_timers();
+        }
+        Ok(())
+    }
 
-                // go to the subroutine
-                self.program_counter = subroutine_address as usize;
+    /// hash of the screen (64 bit FNV-1a of its resolution and pixels), to compare it
+    /// with the one of a known good run
+    pub fn display_hash(&self) -> u64 {
+        let mut hash = fnv1a(FNV_OFFSET, &(self.width() as u16).to_be_bytes());
+        hash = fnv1a(hash, &(self.height() as u16).to_be_bytes());
 
-                debug!("jumping to subroutine at address {}", subroutine_address);
-                trace!(
-                    "corresponding address in the .ch8 file: {}",
-                    subroutine_address - PC_START as u16
-                );
-            }
+        // only the pixels on the screen, 8 per byte
+        for row in self.display[..self.height()].iter() {
+            hash = fnv1a(hash, &row.to_be_bytes()[..self.width() / 8]);
+        }
+        hash
+    }
 
-            // condition: skip the next instruction if the register is equal to a constant
-            0x3000 => {
-                let register_number = (opcode & 0x0F00) >> 8;
-                let constant = opcode & 0x00FF;
+    /// seed the random number generator used by CXNN, e.g. to make a run reproducible
+    pub fn set_seed(&mut self, seed: u64) {
+        // xorshift never leaves 0
+        self.seed = seed.max(1);
+        self.rng = self.seed;
+    }
 
-                debug!(
-                    "checking register number {:X} if {} is equal to the constant {}",
-                    register_number, self.register[register_number as usize], constant
-                );
+    /// get the seed last given to the random number generator, to reproduce a run
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
 
-                if self.register[register_number as usize] == constant as u8 {
-                    // skip the next 2 bytes
-                    self.program_counter += 2;
-                    debug!("test passed, skipping next opcode.");
-                } else {
-                    trace!("test failed.");
-                }
-            }
+    /// reset all key states to unpressed
+    pub fn clear_keys(&mut self) {
+        for key in self.key.iter_mut() {
+            *key = 0;
+        }
+    }
 
-            // condition: skip the next instruction if the register is NOT equal to a constant
-            0x4000 => {
-                let register_number = (opcode & 0x0F00) >> 8;
-                let constant = opcode & 0x00FF;
+    /// mark a key as pressed
+    pub fn register_key(&mut self, key: u8) {
+        self.key[key as usize] = 1;
+    }
 
-                debug!(
-                    "checking register number {:X} if {} is different from the constant {}",
-                    register_number, self.register[register_number as usize], constant
-                );
+    /// get the pressed keys, as one bit per key (bit 0 being the key 0)
+    pub fn keys(&self) -> u16 {
+        self.key
+            .iter()
+            .enumerate()
+            .fold(0, |keys, (i, &state)| keys | (state as u16) << i)
+    }
 
-                if self.register[register_number as usize] != constant as u8 {
-                    // skip the next 2 bytes
-                    self.program_counter += 2;
-                    debug!("test passed, skipping next opcode.");
-                } else {
-                    trace!("test failed.");
-                }
-            }
+    /// press the keys given as one bit per key (bit 0 being the key 0), releasing the others
+    pub fn set_keys(&mut self, keys: u16) {
+        for (i, state) in self.key.iter_mut().enumerate() {
+            *state = (keys >> i) as u8 & 1;
+        }
+    }
 
-            // assign to register
-            0x6000 => {
-                let register_number = (opcode & 0x0F00) >> 8;
-                self.register[register_number as usize] = (opcode & 0x00FF) as u8;
-                debug!(
-                    "assigning {} to register number {:X} ",
-                    self.register[register_number as usize], register_number
-                );
-            }
+    /// get the RPL user flags saved by the program with FX75
+    pub fn rpl(&self) -> [u8; RPL_SIZE] {
+        self.rpl
+    }
 
-            // add to a register
-            0x7000 => {
-                let register_number = (opcode & 0x0F00) >> 8;
-                debug!(
-                    "adding {} to register number {:X} with value {}",
-                    opcode & 0x00FF,
-                    register_number,
-                    self.register[register_number as usize]
-                );
+    /// set the RPL user flags, e.g. the ones saved by a previous run of the program
+    pub fn set_rpl(&mut self, rpl: [u8; RPL_SIZE]) {
+        self.rpl = rpl;
+    }
 
-                // it seems that some roms use register overflowing as a feature, so make sure we don't make rust panic
-                let tmp_register: u16 =
-                    self.register[register_number as usize] as u16 + (opcode & 0x00FF);
+    /// size of the loaded program, in bytes
+    pub fn rom_len(&self) -> usize {
+        self.rom_len
+    }
 
-                self.register[register_number as usize] = (tmp_register % 0x100) as u8;
-                trace!("result: {}", self.register[register_number as usize]);
-            }
+    /// get a copy of the registers, timers, stack and keys, to inspect the machine
+    pub fn debug_state(&self) -> DebugState {
+        DebugState {
+            registers: self.register,
+            program_counter: self.program_counter,
+            index_register: self.index_register,
+            stack: self.stack,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            keys: self.key,
+        }
+    }
 
-            // multiple functions exist here, so we need another match
-            // these functions handle arithmetic operations between registers.
-            0x8000 => {
-                match opcode & 0x000F {
-                    // assign the value of a register to another one
-                    0x0000 => {
-                        let first_register = (opcode & 0x0F00) >> 8;
-                        let second_register = (opcode & 0x0F0) >> 4;
+    /// hash of the loaded program (64 bit FNV-1a), to recognize it.
+    /// The program can modify itself, so it should be called before running it
+    pub fn rom_hash(&self) -> u64 {
+        fnv1a(FNV_OFFSET, &self.memory[PC_START..PC_START + self.rom_len])
+    }
 
-                        debug!(
-                            "setting the register {:X} to {}, the value of the register {:X}",
-                            first_register,
-                            self.register[second_register as usize],
-                            second_register
-                        );
-                        trace!("overwriting {}", self.register[first_register as usize]);
+    /// hash of every instruction run so far, with its address and the registers it left.
+    /// Two runs diverging get different hashes
+    pub fn trace_hash(&self) -> u64 {
+        self.trace_hash
+    }
 
-                        self.register[first_register as usize] =
-                            self.register[second_register as usize];
-                    }
+    /// replace the hexadecimal font used by FX29 with custom glyphs
+    pub fn set_fontset(&mut self, font: [u8; FONTSET_SIZE]) {
+        self.font = font;
+        self.memory[FONT_BASE..FONT_BASE + FONTSET_SIZE].copy_from_slice(&font);
+    }
 
-                    // bitwise OR between two registers
-                    0x0001 => {
-                        let first_register = (opcode & 0x0F00) >> 8;
-                        let second_register = (opcode & 0x0F0) >> 4;
-                        debug!(
-                            "storing bitwise operation {} from {:X} | {} from {:X} to {:X}",
+    /// get the hexadecimal font used by FX29, see set_fontset()
+    pub fn fontset(&self) -> [u8; FONTSET_SIZE] {
+        self.font
+    }
+
+    /// restart the loaded program, as if it had just been loaded. The memory from PC_START
+    /// onward is kept as is, and so are the settings (quirks, font, frozen registers ...)
+    pub fn reset(&mut self) {
+        self.memory[..PC_START]
+            .iter_mut()
+            .for_each(|byte| *byte = 0);
+        self.set_fontset(self.font);
+
+        self.register = [0; REGISTER_NUM];
+        self.program_counter = PC_START;
+        self.index_register = 0;
+        self.display = [0; HIGH_RES_YPX];
+        self.drawn = [0; HIGH_RES_YPX];
+        self.high_res = false;
+        self.dirty = true;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.stack = [0; STACK_LAYERS];
+        self.stack_pointer = 0;
+        self.key = [0; KEY_NUM];
+        self.wait_for_key = false;
+        self.wait_for_key_register = 0;
+        self.self_modifying_writes = 0;
+        self.vf = FlagRegister::Unknown;
+        self.stall = 0;
+        self.trace_hash = FNV_OFFSET;
+        self.pending_time = Duration::new(0, 0);
+        self.timer_phase = 0;
+
+        for (register, &frozen) in self.register.iter_mut().zip(self.frozen_register.iter()) {
+            if let Some(value) = frozen {
+                *register = value;
+            }
+        }
+    }
+
+    /// get the whole memory of the emulator
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// overwrite the memory of the emulator, starting at address 0.
+    /// Nothing prevents writing over the fontset or the running program,
+    /// so arbitrary data can corrupt the execution
+    pub fn load_memory(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        if data.len() > MEM_SIZE {
+            return Err(Chip8Error::MemoryTooLarge(data.len()));
+        }
+
+        self.memory[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// save the state of the machine (memory, registers, display, timers, stack and keys),
+    /// to restore it later with load_state(). The settings of the emulator aren't saved
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_SIZE);
+        state.extend_from_slice(&STATE_MAGIC);
+        state.push(STATE_VERSION);
+
+        state.extend_from_slice(&self.memory);
+        state.extend_from_slice(&self.register);
+        state.extend_from_slice(&(self.program_counter as u16).to_be_bytes());
+        state.extend_from_slice(&self.index_register.to_be_bytes());
+        state.push(self.high_res as u8);
+        for row in self.display.iter() {
+            state.extend_from_slice(&row.to_be_bytes());
+        }
+        state.push(self.delay_timer);
+        state.push(self.sound_timer);
+        for address in self.stack.iter() {
+            state.extend_from_slice(&address.to_be_bytes());
+        }
+        state.push(self.stack_pointer as u8);
+        state.extend_from_slice(&self.key);
+        state.push(self.wait_for_key as u8);
+        state.push(self.wait_for_key_register as u8);
+        state.extend_from_slice(&(self.rom_len as u16).to_be_bytes());
+
+        state
+    }
+
+    /// restore a state made by save_state(). Nothing changes if the state is invalid,
+    /// or comes from another version of the emulator
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        if data.len() < STATE_MAGIC.len() + 1 || data[..STATE_MAGIC.len()] != STATE_MAGIC {
+            return Err(Chip8Error::InvalidState("not a save state".to_string()));
+        }
+        if data[STATE_MAGIC.len()] != STATE_VERSION {
+            return Err(Chip8Error::InvalidState(format!(
+                "unsupported version {}",
+                data[STATE_MAGIC.len()]
+            )));
+        }
+        if data.len() != STATE_SIZE {
+            return Err(Chip8Error::InvalidState(format!(
+                "{} bytes long instead of {}",
+                data.len(),
+                STATE_SIZE
+            )));
+        }
+
+        // read the fields one after the other
+        let mut position = STATE_MAGIC.len() + 1;
+        let mut take = move |len: usize| {
+            position += len;
+            &data[position - len..position]
+        };
+        let be_u16 = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+
+        let memory = take(MEM_SIZE);
+        let register = take(REGISTER_NUM);
+        let program_counter = be_u16(take(2)) as usize;
+        let index_register = be_u16(take(2));
+        let high_res = take(1)[0];
+        let display = take(16 * HIGH_RES_YPX);
+        let timers = take(2);
+        let stack = take(2 * STACK_LAYERS);
+        let stack_pointer = take(1)[0] as usize;
+        let key = take(KEY_NUM);
+        let key_wait = take(2);
+        let rom_len = be_u16(take(2)) as usize;
+
+        if program_counter >= MEM_SIZE
+            || high_res > 1
+            || stack_pointer > STACK_LAYERS
+            || key.iter().any(|&state| state > 1)
+            || key_wait[1] as usize >= REGISTER_NUM
+            || rom_len > MEM_SIZE - PC_START
+        {
+            return Err(Chip8Error::InvalidState("corrupted".to_string()));
+        }
+
+        self.memory.copy_from_slice(memory);
+        self.register.copy_from_slice(register);
+        self.program_counter = program_counter;
+        self.index_register = index_register;
+        self.high_res = high_res == 1;
+        self.dirty = true;
+        for (row, bytes) in self.display.iter_mut().zip(display.chunks(16)) {
+            let mut row_bytes = [0; 16];
+            row_bytes.copy_from_slice(bytes);
+            *row = u128::from_be_bytes(row_bytes);
+        }
+        self.drawn = [0; HIGH_RES_YPX];
+        self.delay_timer = timers[0];
+        self.sound_timer = timers[1];
+        for (address, bytes) in self.stack.iter_mut().zip(stack.chunks(2)) {
+            *address = be_u16(bytes);
+        }
+        self.stack_pointer = stack_pointer;
+        self.key.copy_from_slice(key);
+        self.wait_for_key = key_wait[0] == 1;
+        self.wait_for_key_register = key_wait[1] as usize;
+        self.rom_len = rom_len;
+        self.vf = FlagRegister::Unknown;
+        self.stall = 0;
+
+        Ok(())
+    }
+
+    /// get the compatibility behaviors in use
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// change the compatibility behaviors
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// log every conditional skip on a single line, starting with "branch",
+    /// to follow the control flow of a program
+    pub fn set_trace_branches(&mut self, trace_branches: bool) {
+        self.trace_branches = trace_branches;
+    }
+
+    /// warn when the program reads VF after a flag (carry, borrow, shifted bit or collision)
+    /// overwrote a value it stored there. This is a guess: a program can also store
+    /// a value in VF on purpose before testing a flag, without a conditional skip
+    pub fn set_warn_vf_reuse(&mut self, warn_vf_reuse: bool) {
+        self.warn_vf_reuse = warn_vf_reuse;
+    }
+
+    /// make emulate() return an error when the program misbehaves in a way that can be
+    /// worked around, e.g. a return without a subroutine. By default, a warning is logged
+    /// and the faulty instruction is skipped
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// whether misbehaving programs get an error, see set_strict()
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// skip the opcodes the emulator can't run with a warning, instead of returning
+    /// an error, e.g. to get further in a program while debugging it
+    pub fn set_ignore_unknown(&mut self, ignore_unknown: bool) {
+        self.ignore_unknown = ignore_unknown;
+    }
+
+    /// whether unknown opcodes are skipped, see set_ignore_unknown()
+    pub fn ignore_unknown(&self) -> bool {
+        self.ignore_unknown
+    }
+
+    /// make a jump to itself (1NNN) return an error, to find where a program gets stuck.
+    /// The program counter stays on the jump
+    pub fn set_trap_halt(&mut self, trap_halt: bool) {
+        self.trap_halt = trap_halt;
+    }
+
+    /// set how many cycles a draw (DXYN) takes, 1 being the cost of any other instruction.
+    /// Programs timing their flicker by the instructions run between draws
+    /// flicker slower with a higher cost
+    pub fn set_draw_cost(&mut self, draw_cost: u32) {
+        self.draw_cost = draw_cost.max(1);
+    }
+
+    /// get the cycles a draw takes, see set_draw_cost()
+    pub fn draw_cost(&self) -> u32 {
+        self.draw_cost
+    }
+
+    /// set how many instructions advance() runs per second (60 by default, 1GHz at most).
+    /// The timers keep counting down at 60Hz
+    pub fn set_clock_hz(&mut self, clock_hz: u32) {
+        self.clock_hz = clock_hz.clamp(1, MAX_CLOCK_HZ);
+    }
+
+    /// get the instructions run per second by advance(), see set_clock_hz()
+    pub fn clock_hz(&self) -> u32 {
+        self.clock_hz
+    }
+
+    /// forbid (or allow again) the program to write to the fontset and reserved memory,
+    /// to catch programs corrupting it
+    pub fn set_protect_reserved(&mut self, protect_reserved: bool) {
+        self.protect_reserved = protect_reserved;
+    }
+
+    /// whether the fontset and reserved memory are protected, see set_protect_reserved()
+    pub fn protect_reserved(&self) -> bool {
+        self.protect_reserved
+    }
+
+    /// whether the chip8 is making a sound, i.e. the sound timer is counting down
+    pub fn is_beeping(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// whether one of the timers is still counting down
+    pub fn timers_active(&self) -> bool {
+        self.delay_timer > 0 || self.sound_timer > 0
+    }
+
+    /// whether the program is blocked until a key is pressed (FX0A)
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.wait_for_key
+    }
+
+    /// lock a register to a value, so that the program can't change it.
+    /// Indexes past VF are ignored
+    pub fn freeze_register(&mut self, index: usize, value: u8) {
+        if index < REGISTER_NUM {
+            self.frozen_register[index] = Some(value);
+            self.register[index] = value;
+        }
+    }
+
+    /// get the value each register is locked to, if any, see freeze_register()
+    pub fn frozen_registers(&self) -> [Option<u8>; REGISTER_NUM] {
+        self.frozen_register
+    }
+
+    /// let the program change a frozen register again. Indexes past VF are ignored
+    pub fn unfreeze_register(&mut self, index: usize) {
+        if index < REGISTER_NUM {
+            self.frozen_register[index] = None;
+        }
+    }
+
+    /// emulate one step of the chip8, running a called subroutine to completion
+    pub fn step_over(&mut self) -> Result<(), Chip8Error> {
+        let opcode = self.fetch()?;
+        let depth = self.stack_pointer;
+        self.emulate()?;
+
+        if opcode & 0xF000 == 0x2000 {
+            self.run_until_depth(depth)?;
+        }
+        Ok(())
+    }
+
+    /// emulate until the current subroutine returns
+    pub fn step_out(&mut self) -> Result<(), Chip8Error> {
+        if self.stack_pointer == 0 {
+            return Err(Chip8Error::NotInSubroutine);
+        }
+
+        self.run_until_depth(self.stack_pointer - 1)
+    }
+
+    /// make run_until_break() stop before running the instruction at an address
+    pub fn set_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    /// remove a breakpoint set with set_breakpoint()
+    pub fn clear_breakpoint(&mut self, address: usize) {
+        self.breakpoints.remove(&address);
+    }
+
+    /// emulate until the program counter reaches a breakpoint, and return its address.
+    /// At least one instruction is run, so continuing from a breakpoint doesn't stop
+    /// on it again right away. Fails if the program waits for a key, as it would
+    /// never get there otherwise
+    pub fn run_until_break(&mut self) -> Result<usize, Chip8Error> {
+        for _ in 0..MAX_STEP_CYCLES {
+            self.emulate()?;
+
+            if self.breakpoints.contains(&self.program_counter) {
+                return Ok(self.program_counter);
+            }
+            if self.wait_for_key {
+                return Err(Chip8Error::WaitingForKey(self.current_address()));
+            }
+        }
+
+        Err(Chip8Error::StepLimit(MAX_STEP_CYCLES))
+    }
+
+    // emulate until the stack gets back to the given depth.
+    // also stops if the program waits for a key, as it would never return otherwise
+    fn run_until_depth(&mut self, depth: usize) -> Result<(), Chip8Error> {
+        for _ in 0..MAX_STEP_CYCLES {
+            if self.stack_pointer <= depth || self.wait_for_key {
+                return Ok(());
+            }
+            self.emulate()?;
+        }
+
+        Err(Chip8Error::StepLimit(MAX_STEP_CYCLES))
+    }
+
+    // get whether a key is pressed (1) or not (0), on behalf of the program.
+    // A key past the 16 of the keypad can't be pressed
+    fn key_state(&self, keycode: u8) -> u8 {
+        self.key.get(keycode as usize).copied().unwrap_or(0)
+    }
+
+    // read memory on behalf of the program
+    fn read_mem(&self, address: usize) -> Result<u8, Chip8Error> {
+        self.memory
+            .get(address)
+            .copied()
+            .ok_or(Chip8Error::OutOfBounds(address))
+    }
+
+    // write to memory on behalf of the program
+    fn write_mem(&mut self, address: usize, value: u8) -> Result<(), Chip8Error> {
+        if address >= MEM_SIZE {
+            return Err(Chip8Error::OutOfBounds(address));
+        }
+
+        if self.protect_reserved && address < PC_START {
+            return Err(Chip8Error::ProtectedWrite(address));
+        }
+
+        if address >= PC_START && address < PC_START + self.rom_len {
+            self.self_modifying_writes += 1;
+        }
+
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    // log a conditional skip if needed. For the key opcodes,
+    // the compared values are the keycode and the key state
+    fn trace_branch(&self, opcode: u16, left: u8, right: u8, skip: bool) {
+        if self.trace_branches {
+            info!(
+                "branch pc={:03X} opcode={:04X} left={:02X} right={:02X} skipped={}",
+                self.current_address(),
+                opcode,
+                left,
+                right,
+                skip
+            );
+        }
+    }
+
+    // warn if the opcode about to run reads a value of the program in VF which got
+    // replaced by a flag. Flags are usually tested with a conditional skip, which is allowed.
+    // Returns whether a warning was given
+    fn check_vf_reuse(&mut self, opcode: u16) -> bool {
+        let mut warned = false;
+
+        if let FlagRegister::Clobbered(address, flag_opcode) = self.vf {
+            if reads_register(opcode, 15) {
+                warn!(
+                    "VF read by {:04X} at {:03X}, but the value stored there was overwritten by the flag of {:04X} at {:03X}",
+                    opcode, self.program_counter, flag_opcode, address
+                );
+                self.vf = FlagRegister::Unknown;
+                warned = true;
+            } else if compares_register(opcode, 15) {
+                self.vf = FlagRegister::Unknown;
+            }
+        }
+
+        // with the quirk, the logic operations clear VF as if it held a flag
+        let logic = opcode & 0xF000 == 0x8000 && matches!(opcode & 0x000F, 0x1..=0x3);
+        if sets_flag(opcode) || (logic && self.quirks.vf_reset_on_logic) {
+            if self.vf == FlagRegister::Value {
+                self.vf = FlagRegister::Clobbered(self.program_counter, opcode);
+            }
+        } else if sets_register(opcode, 15) {
+            self.vf = FlagRegister::Value;
+        }
+
+        warned
+    }
+
+    // with the shift_uses_vy quirk, 8XY6 and 8XYE shift VY into VX (COSMAC VIP):
+    // copy VY to VX, which then gets shifted in place as without the quirk
+    fn copy_shift_source(&mut self, opcode: u16) {
+        if self.quirks.shift_uses_vy {
+            let source = ((opcode & 0x00F0) >> 4) as usize;
+            self.register[((opcode & 0x0F00) >> 8) as usize] = self.register[source];
+        }
+    }
+
+    // fail on an opcode the emulator can't run, or skip it with a warning if told to.
+    // The program counter is already past it
+    fn unknown_opcode(&self, opcode: u16) -> Result<(), Chip8Error> {
+        if !self.ignore_unknown {
+            return Err(Chip8Error::UnknownOpcode(opcode));
+        }
+
+        warn!("warning: ran into unknown opcode: {:X}", opcode);
+        Ok(())
+    }
+
+    // with the increment_index_on_load_store quirk, leave I after the last register
+    // stored or loaded by FX55 or FX65 (COSMAC VIP). The caller made sure the registers
+    // fit in memory, so I stays below MEM_SIZE
+    fn increment_index_after_load_store(&mut self, registers: u16) {
+        if self.quirks.increment_index_on_load_store {
+            self.index_register += registers + 1;
+        }
+    }
+
+    // clear VF after a logic operation (8XY1, 8XY2, 8XY3) if the quirk asks for it,
+    // as the COSMAC VIP did
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.vf_reset_on_logic {
+            self.register[15] = 0;
+        }
+    }
+
+    // get a random byte from the xorshift64* generator; see
+    // https://en.wikipedia.org/wiki/Xorshift#xorshift*
+    fn random(&mut self) -> u8 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+
+        // the high bits are the most random ones
+        (self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    // get the opcode at the program counter
+    fn fetch(&self) -> Result<u16, Chip8Error> {
+        // opcodes are 2 bytes long.
+        // get the first byte, shift by a byte, combine with the second byte.
+        // An opcode on the last byte of memory ends on the first one
+        Ok((self.read_mem(self.program_counter)? as u16) << 8
+            | self.read_mem((self.program_counter + 1) % MEM_SIZE)? as u16)
+    }
+
+    // move the program counter to the next opcode. Addresses are 12 bits long, so
+    // a program running off the end of the memory goes on from address 0
+    fn next_instruction(&mut self) {
+        self.program_counter = (self.program_counter + 2) % MEM_SIZE;
+    }
+
+    // address of the opcode being run, the program counter being already past it
+    fn current_address(&self) -> usize {
+        (self.program_counter + MEM_SIZE - 2) % MEM_SIZE
+    }
+
+    /// emulate the chip8 for the given elapsed time, e.g. the frame time of a game engine.
+    /// Runs set_clock_hz() instructions per second and ticks the timers at 60Hz;
+    /// the time left over is kept for the next call
+    pub fn advance(&mut self, dt: Duration) -> Result<(), Chip8Error> {
+        let cycle_time = Duration::from_secs(1) / self.clock_hz;
+        self.pending_time += dt;
+
+        while self.pending_time >= cycle_time {
+            self.pending_time -= cycle_time;
+            self.emulate()?;
+
+            self.timer_phase += TIMER_HZ;
+            while self.timer_phase >= self.clock_hz {
+                self.timer_phase -= self.clock_hz;
+                self.tick_timers();
+            }
+        }
+        Ok(())
+    }
+
+    /// decrement both timers. They count down at 60Hz, whatever the number of
+    /// instructions run in the meantime: this should be called once per 1/60s
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    /// emulate one step of the chip8. The timers aren't ticked, see tick_timers()
+    pub fn emulate(&mut self) -> Result<(), Chip8Error> {
+        self.step()
+    }
+
+    /// run exactly one instruction (fetch, decode and execute), or wait for a key
+    /// or a draw to finish. Nothing else changes: the timers are left untouched
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        // get the opcode, which corresponds to a processor instruction. see:
+        // https://en.wikipedia.org/wiki/CHIP-8
+        // for an exhaustive list.
+
+        // we might need to stop the program until a certain key is pressed
+
+        if self.wait_for_key {
+            for (keycode, &key_state) in self.key.iter().enumerate() {
+                // a key is pressed
+                if key_state == 1 {
+                    self.wait_for_key = false;
+                    debug!("got key {}", keycode);
+                    self.register[self.wait_for_key_register] = keycode as u8;
+                    break;
+                }
+            }
+
+            // still no key pressed, return from the function
+            if self.wait_for_key {
+                return Ok(());
+            }
+
+            // help debugging programs which use the keycode right away,
+            // without making sure it's one they expect
+            let opcode = self.fetch()?;
+            if compares_register(opcode, self.wait_for_key_register) {
+                trace!(
+                    "the key stored in register {:X} is compared right away by {:04X}",
+                    self.wait_for_key_register,
+                    opcode
+                );
+            }
+        }
+
+        // the last draw isn't over yet
+        if self.stall > 0 {
+            self.stall -= 1;
+            return Ok(());
+        }
+
+        let address = self.program_counter;
+        let opcode = self.fetch()?;
+
+        // machine readable lines: an event name followed by key=value fields,
+        // the values being hexadecimal
+        debug!(
+            "cycle pc={:03X} opcode={:04X}",
+            self.program_counter, opcode
+        );
+
+        // state before the instruction, to log what it changed
+        let before = if log_enabled!(Level::Debug) {
+            Some((self.register, self.index_register))
+        } else {
+            None
+        };
+
+        if self.warn_vf_reuse {
+            self.check_vf_reuse(opcode);
+        }
+
+        // increase the program counter for the next opcode
+        self.next_instruction();
+
+        // only query the clock if we're profiling the interpreter
+        let start = self.profile.as_ref().map(|_| Instant::now());
+
+        // process our opcode here
+        match opcode & 0xF000 {
+            // multiple functions exist here, so we need another match
+            0x0000 => {
+                match opcode {
+                    // return from a subroutine
+                    0x00EE => {
+                        // don't jump to a bogus address if there is nothing to return from:
+                        // skip the return, unless we are strict
+                        if self.stack_pointer == 0 {
+                            if self.strict {
+                                return Err(Chip8Error::StackUnderflow);
+                            }
+                            warn!(
+                                "stack underflow: no subroutine to exit at address {:X} !",
+                                self.current_address()
+                            );
+                        } else {
+                            // jump back to the right address
+                            self.stack_pointer -= 1;
+                            self.program_counter = self.stack[self.stack_pointer] as usize;
+                            debug!("exiting subroutine.");
+                        }
+                    }
+
+                    // clear the display. HIRES CHIP-8 programs call their interpreter
+                    // patch to do so instead
+                    _ if opcode == 0x00E0 || self.hires && opcode == HIRES_CLEAR_OPCODE => {
+                        self.display = [0; HIGH_RES_YPX];
+                        self.drawn = [0; HIGH_RES_YPX];
+                        self.dirty = true;
+                        debug!("cleared display.");
+                    }
+
+                    // switch to the low (64x32) or high (128x64) resolution of the
+                    // SUPER-CHIP, which clears the display unless asked otherwise
+                    0x00FE | 0x00FF => {
+                        self.high_res = opcode == 0x00FF;
+                        if !self.quirks.keep_display_on_resolution_switch {
+                            self.display = [0; HIGH_RES_YPX];
+                            self.drawn = [0; HIGH_RES_YPX];
+                        }
+                        self.dirty = true;
+                        debug!("switched to a {}x{} display.", self.width(), self.height());
+                    }
+
+                    // scroll the display up by N pixels (SUPER-CHIP 1.1 / XO-CHIP).
+                    // N is counted in high resolution pixels, so in low resolution
+                    // we scroll by half the amount
+                    _ if opcode & 0xFFF0 == 0x00D0 => {
+                        let rows = (opcode & 0x000F) as usize * self.height() / HIGH_RES_YPX;
+                        let height = self.height();
+                        self.display[..height].copy_within(rows.., 0);
+                        for row in self.display[height - rows..height].iter_mut() {
+                            *row = 0;
+                        }
+                        self.dirty = true;
+                        debug!("scrolled display up by {} rows.", rows);
+                    }
+
+                    // scroll the display down by N pixels (SUPER-CHIP 1.1), halved
+                    // in low resolution like 00DN
+                    _ if opcode & 0xFFF0 == 0x00C0 => {
+                        let rows = (opcode & 0x000F) as usize * self.height() / HIGH_RES_YPX;
+                        let height = self.height();
+                        self.display[..height].copy_within(..height - rows, rows);
+                        for row in self.display[..rows].iter_mut() {
+                            *row = 0;
+                        }
+                        self.dirty = true;
+                        debug!("scrolled display down by {} rows.", rows);
+                    }
+
+                    // scroll the display right or left by 4 pixels (SUPER-CHIP 1.1),
+                    // so by 2 pixels in low resolution like 00DN. The leftmost pixel
+                    // being the most significant bit, scrolling right is a right shift
+                    0x00FB | 0x00FC => {
+                        let columns = 4 * self.width() / HIGH_RES_XPX;
+                        let mask = self.row_mask();
+                        for row in self.display.iter_mut() {
+                            *row = if opcode == 0x00FB {
+                                *row >> columns
+                            } else {
+                                *row << columns
+                            } & mask;
+                        }
+                        self.dirty = true;
+                        debug!(
+                            "scrolled display {} by {} columns.",
+                            if opcode == 0x00FB { "right" } else { "left" },
+                            columns
+                        );
+                    }
+
+                    _ => self.unknown_opcode(opcode)?,
+                }
+            }
+
+            // jump
+            0x1000 => {
+                let jump_address = opcode & 0x0FFF;
+
+                if self.trap_halt && jump_address as usize == self.current_address() {
+                    self.program_counter = jump_address as usize;
+                    return Err(Chip8Error::Halt(jump_address as usize));
+                }
+
+                self.program_counter = jump_address as usize;
+                debug!("jumping to address {}", jump_address);
+                // the reserved memory below the program isn't part of the file
+                if jump_address >= PC_START as u16 {
+                    trace!(
+                        "corresponding address in the .ch8 file: {}",
+                        jump_address - PC_START as u16
+                    );
+                }
+            }
+
+            // call a subroutine
+            0x2000 => {
+                // where is the subroutine to call
+                let subroutine_address = opcode & 0x0FFF;
+
+                // don't write past the stack
+                if self.stack_pointer == STACK_LAYERS {
+                    return Err(Chip8Error::StackOverflow);
+                }
+
+                // store on the stack where to return after the subroutine exited
+                self.stack[self.stack_pointer] = self.program_counter as u16;
+                self.stack_pointer += 1;
+
+                // go to the subroutine
+                self.program_counter = subroutine_address as usize;
+
+                debug!("jumping to subroutine at address {}", subroutine_address);
+                // the reserved memory below the program isn't part of the file
+                if subroutine_address >= PC_START as u16 {
+                    trace!(
+                        "corresponding address in the .ch8 file: {}",
+                        subroutine_address - PC_START as u16
+                    );
+                }
+            }
+
+            // condition: skip the next instruction if the register is equal to a constant
+            0x3000 => {
+                let register_number = (opcode & 0x0F00) >> 8;
+                let constant = opcode & 0x00FF;
+
+                debug!(
+                    "checking register number {:X} if {} is equal to the constant {}",
+                    register_number, self.register[register_number as usize], constant
+                );
+
+                let skip = self.register[register_number as usize] == constant as u8;
+                self.trace_branch(
+                    opcode,
+                    self.register[register_number as usize],
+                    constant as u8,
+                    skip,
+                );
+
+                if skip {
+                    // skip the next 2 bytes
+                    self.next_instruction();
+                    debug!("test passed, skipping next opcode.");
+                } else {
+                    trace!("test failed.");
+                }
+            }
+
+            // condition: skip the next instruction if the register is NOT equal to a constant
+            0x4000 => {
+                let register_number = (opcode & 0x0F00) >> 8;
+                let constant = opcode & 0x00FF;
+
+                debug!(
+                    "checking register number {:X} if {} is different from the constant {}",
+                    register_number, self.register[register_number as usize], constant
+                );
+
+                let skip = self.register[register_number as usize] != constant as u8;
+                self.trace_branch(
+                    opcode,
+                    self.register[register_number as usize],
+                    constant as u8,
+                    skip,
+                );
+
+                if skip {
+                    // skip the next 2 bytes
+                    self.next_instruction();
+                    debug!("test passed, skipping next opcode.");
+                } else {
+                    trace!("test failed.");
+                }
+            }
+
+            // skip the next instruction if two registers are equal
+            0x5000 if opcode & 0x000F == 0 => {
+                let first_register = (opcode & 0x0F00) >> 8;
+                let second_register = (opcode & 0x00F0) >> 4;
+
+                debug!(
+                    "checking register number {:X} if {} is equal to register number {:X} with {}",
+                    first_register,
+                    self.register[first_register as usize],
+                    second_register,
+                    self.register[second_register as usize]
+                );
+
+                let skip = self.register[first_register as usize]
+                    == self.register[second_register as usize];
+                self.trace_branch(
+                    opcode,
+                    self.register[first_register as usize],
+                    self.register[second_register as usize],
+                    skip,
+                );
+
+                if skip {
+                    // skip the next 2 bytes
+                    self.next_instruction();
+                    debug!("test passed, skipping next opcode.");
+                } else {
+                    trace!("test failed.");
+                }
+            }
+
+            // assign to register
+            0x6000 => {
+                let register_number = (opcode & 0x0F00) >> 8;
+                self.register[register_number as usize] = (opcode & 0x00FF) as u8;
+                debug!(
+                    "assigning {} to register number {:X} ",
+                    self.register[register_number as usize], register_number
+                );
+            }
+
+            // add to a register
+            0x7000 => {
+                let register_number = (opcode & 0x0F00) >> 8;
+                debug!(
+                    "adding {} to register number {:X} with value {}",
+                    opcode & 0x00FF,
+                    register_number,
+                    self.register[register_number as usize]
+                );
+
+                // it seems that some roms use register overflowing as a feature, so make sure we don't make rust panic
+                let tmp_register: u16 =
+                    self.register[register_number as usize] as u16 + (opcode & 0x00FF);
+
+                self.register[register_number as usize] = (tmp_register % 0x100) as u8;
+                trace!("result: {}", self.register[register_number as usize]);
+            }
+
+            // multiple functions exist here, so we need another match
+            // these functions handle arithmetic operations between registers.
+            0x8000 => {
+                match opcode & 0x000F {
+                    // assign the value of a register to another one
+                    0x0000 => {
+                        let first_register = (opcode & 0x0F00) >> 8;
+                        let second_register = (opcode & 0x0F0) >> 4;
+
+                        debug!(
+                            "setting the register {:X} to {}, the value of the register {:X}",
+                            first_register,
+                            self.register[second_register as usize],
+                            second_register
+                        );
+                        trace!("overwriting {}", self.register[first_register as usize]);
+
+                        self.register[first_register as usize] =
+                            self.register[second_register as usize];
+                    }
+
+                    // bitwise OR between two registers
+                    0x0001 => {
+                        let first_register = (opcode & 0x0F00) >> 8;
+                        let second_register = (opcode & 0x0F0) >> 4;
+                        debug!(
+                            "storing bitwise operation {} from {:X} | {} from {:X} to {:X}",
+                            self.register[first_register as usize],
+                            first_register,
+                            self.register[second_register as usize],
+                            second_register,
+                            first_register
+                        );
+                        self.register[first_register as usize] |=
+                            self.register[second_register as usize];
+                        trace!("result: {}", self.register[first_register as usize]);
+                        self.reset_vf_after_logic();
+                    }
+
+                    // bitwise AND between two registers
+                    0x0002 => {
+                        let first_register = (opcode & 0x0F00) >> 8;
+                        let second_register = (opcode & 0x0F0) >> 4;
+                        debug!(
+                            "storing bitwise operation {} from {:X} & {} from {:X} to {:X}",
+                            self.register[first_register as usize],
+                            first_register,
+                            self.register[second_register as usize],
+                            second_register,
+                            first_register
+                        );
+                        self.register[first_register as usize] &=
+                            self.register[second_register as usize];
+                        trace!("result: {}", self.register[first_register as usize]);
+                        self.reset_vf_after_logic();
+                    }
+
+                    // bitwise XOR between two registers
+                    0x0003 => {
+                        let first_register = (opcode & 0x0F00) >> 8;
+                        let second_register = (opcode & 0x0F0) >> 4;
+                        debug!(
+                            "storing bitwise operation {} from {:X} ^ {} from {:X} to {:X}",
                             self.register[first_register as usize],
                             first_register,
                             self.register[second_register as usize],
                             second_register,
                             first_register
                         );
-                        self.register[first_register as usize] |=
-                            self.register[second_register as usize];
-                        trace!("result: {}", self.register[first_register as usize]);
+                        self.register[first_register as usize] ^=
+                            self.register[second_register as usize];
+                        trace!("result: {}", self.register[first_register as usize]);
+                        self.reset_vf_after_logic();
+                    }
+
+                    // add one register to another
+                    0x0004 => {
+                        let first_register = (opcode & 0x0F00) >> 8;
+                        let second_register = (opcode & 0x0F0) >> 4;
+                        debug!(
+                            "adding {} to register {:X} containing {} from register {:X}",
+                            self.register[second_register as usize],
+                            first_register,
+                            self.register[first_register as usize],
+                            second_register
+                        );
+
+                        let mut res: u16 = self.register[first_register as usize] as u16
+                            + self.register[second_register as usize] as u16;
+                        // the result takes 9 bit: store the MSB in the carry flag register
+                        if res > 255 {
+                            debug!("{} is a 9 bit result, setting the carry flag", res);
+                            // store the first in the F register
+                            self.register[15] = 1;
+                            // discard the firt bit to have a valid 8 bit variable
+                            res &= 0b011111111;
+                            trace!("new 8 bit result (without the carry bit): {}", res);
+                        } else {
+                            debug!("result: {}", res);
+                        }
+
+                        self.register[first_register as usize] = res as u8;
+                    }
+
+                    // substract the first register by the second register
+                    0x0005 => {
+                        let first_register = (opcode & 0x0F00) >> 8;
+                        let second_register = (opcode & 0x0F0) >> 4;
+
+                        let first = self.register[first_register as usize];
+                        let second = self.register[second_register as usize];
+
+                        // wrap around on borrow, as the hardware does. The borrow flag
+                        // is written last, so that it wins over the result when the
+                        // first register is VF
+                        self.register[first_register as usize] = first.wrapping_sub(second);
+
+                        // set the borrow flag if the second register is greater than the first one
+                        if second > first {
+                            self.register[15] = 0; // it's a bit confusing: 0 means borrowing
+                        } else {
+                            self.register[15] = 1;
+                        }
+                    }
+
+                    // stores LSB in register F and shift the register to the right
+                    0x0006 => {
+                        let register_number = (opcode & 0x0F00) >> 8;
+                        self.copy_shift_source(opcode);
+                        debug!(
+                            "shifting right by one {} in {:X}",
+                            self.register[register_number as usize], register_number
+                        );
+                        // store the lsb in the F register
+                        self.register[15] = self.register[register_number as usize] & 1;
+                        debug!("lsb {} stored in register F", self.register[15]);
+
+                        // store the shift back in the register
+                        self.register[register_number as usize] >>= 1;
+                        debug!("result: {}", self.register[register_number as usize]);
                     }
 
-                    // bitwise AND between two registers
-                    0x0002 => {
+                    // subtract the first register from the second one, in the first one
+                    0x0007 => {
                         let first_register = (opcode & 0x0F00) >> 8;
                         let second_register = (opcode & 0x0F0) >> 4;
+                        let first = self.register[first_register as usize];
+                        let second = self.register[second_register as usize];
+
+                        // the borrow flag is written last, so that it wins over the
+                        // result when the first register is VF
+                        self.register[first_register as usize] = second.wrapping_sub(first);
+
+                        // set the borrow flag if the first register is greater than the second one
+                        if first > second {
+                            self.register[15] = 0; // same as above: 0 means borrowing
+                        } else {
+                            self.register[15] = 1;
+                        }
+                    }
+                    // stores MSB in register F and shift the register to the left
+                    0x000E => {
+                        let register_number = (opcode & 0x0F00) >> 8;
+                        self.copy_shift_source(opcode);
                         debug!(
-                            "storing bitwise operation {} from {:X} & {} from {:X} to {:X}",
-                            self.register[first_register as usize],
-                            first_register,
-                            self.register[second_register as usize],
-                            second_register,
-                            first_register
+                            "shifting left by one {} in {:X}",
+                            self.register[register_number as usize], register_number
                         );
-                        self.register[first_register as usize] &=
-                            self.register[second_register as usize];
-                        trace!("result: {}", self.register[first_register as usize]);
+                        // store the msb in the F register
+                        self.register[15] =
+                            (self.register[register_number as usize] & 0b10000000) >> 7;
+                        debug!("msb {} stored in register F", self.register[15]);
+
+                        // store the shift back in the register
+                        self.register[register_number as usize] <<= 1;
+                        debug!("result: {}", self.register[register_number as usize]);
                     }
 
-                    // bitwise XOR between two registers
-                    0x0003 => {
-                        let first_register = (opcode & 0x0F00) >> 8;
-                        let second_register = (opcode & 0x0F0) >> 4;
+                    _ => self.unknown_opcode(opcode)?,
+                }
+            }
+
+            // skip the next instruction if two registers are different
+            0x9000 if opcode & 0x000F == 0 => {
+                let first_register = (opcode & 0x0F00) >> 8;
+                let second_register = (opcode & 0x00F0) >> 4;
+
+                debug!(
+                    "checking register number {:X} if {} is different from register number {:X} with {}",
+                    first_register,
+                    self.register[first_register as usize],
+                    second_register,
+                    self.register[second_register as usize]
+                );
+
+                let skip = self.register[first_register as usize]
+                    != self.register[second_register as usize];
+                self.trace_branch(
+                    opcode,
+                    self.register[first_register as usize],
+                    self.register[second_register as usize],
+                    skip,
+                );
+
+                if skip {
+                    // skip the next 2 bytes
+                    self.next_instruction();
+                    debug!("test passed, skipping next opcode.");
+                } else {
+                    trace!("test failed.");
+                }
+            }
+
+            // set the value of the index register
+            0xA000 => {
+                self.index_register = opcode & 0x0FFF;
+                debug!("setting index register to {}", self.index_register);
+            }
+
+            // jump to an address offset by the register 0 (e.g. in a jump table)
+            0xB000 => {
+                // addresses are 12 bits long, so the offset wraps around the memory
+                let jump_address =
+                    ((opcode & 0x0FFF) as usize + self.register[0] as usize) % MEM_SIZE;
+
+                self.program_counter = jump_address;
+                debug!("jumping to address {}", jump_address);
+            }
+
+            // assign a random number, masked by a constant, to a register
+            0xC000 => {
+                let register_number = (opcode & 0x0F00) >> 8;
+                self.register[register_number as usize] = self.random() & (opcode & 0x00FF) as u8;
+                debug!(
+                    "assigning the random number {} to register number {:X}",
+                    self.register[register_number as usize], register_number
+                );
+            }
+
+            // draw to the screen
+            0xD000 => {
+                // get the x coordinate of where to draw on the display. The starting
+                // position wraps around the screen, whether the sprite wraps or not
+                let x =
+                    self.register[((opcode & 0x0F00) >> 8) as usize] as u16 % self.width() as u16;
+                // get the y coordinate
+                let y =
+                    self.register[((opcode & 0x00F0) >> 4) as usize] as u16 % self.height() as u16;
+                debug!("starting drawing operation at ({};{})", x, y);
+
+                // sprite size. A height of 0 draws a 16x16 sprite in high resolution
+                // (SUPER-CHIP). Otherwise, interpreters disagree on what it means:
+                // either nothing gets drawn, or a 8x16 sprite
+                let (width, height) = match opcode & 0x000F {
+                    0 if self.high_res => (16, 16),
+                    0 if self.quirks.draw_zero_height => (8, 16),
+                    height => (8, height),
+                };
+                trace!("size of the drawing: {}x{}", width, height);
+
+                // bytes of sprite data per row
+                let row_bytes = width / 8;
+
+                // the index register can point anywhere, make sure the sprite is in memory
+                let index = self.index_register as usize;
+                if index + row_bytes * height as usize > MEM_SIZE {
+                    return Err(Chip8Error::OutOfBounds(index));
+                }
+
+                // clear the F register; it's going to be used for collision detection.
+                self.register[15] = 0;
+                self.drawn = [0; HIGH_RES_YPX];
+                self.dirty = true;
+                self.stall = self.draw_cost - 1;
+
+                let wrap = self.quirks.wrap_sprites;
+                for dy in 0..height {
+                    // make sure we're not drawing out of the screen, or wrap around it
+                    let mut i = y + dy;
+                    if i >= self.height() as u16 {
+                        if !wrap {
+                            trace!("attempt to draw out of the screen catched !");
+                            continue;
+                        }
+                        i -= self.height() as u16;
+                    }
+
+                    // get the pixels data from the memory, using the index register
+                    let start = index + row_bytes * dy as usize;
+                    let px_row = (start..start + row_bytes).try_fold(0, |row, address| {
+                        Ok(row << 8 | self.read_mem(address)? as u128)
+                    })?;
+
+                    // move the sprite row to the leftmost pixels of a display row, then
+                    // to its x coordinate. The pixels going past the right edge are
+                    // shifted out or masked, which clips the sprite.
+                    let leftmost = px_row << (HIGH_RES_XPX - width);
+                    let mut sprite_row = leftmost >> x;
+                    // otherwise, they are shifted back to the left edge
+                    if wrap {
+                        sprite_row |= leftmost
+                            .checked_shl(self.width() as u32 - x as u32)
+                            .unwrap_or(0);
+                    }
+                    let sprite_row = sprite_row & self.row_mask();
+
+                    // remember which pixels are getting turned on
+                    self.drawn[i as usize] |= sprite_row & !self.display[i as usize];
+
+                    // collision detected
+                    if self.display[i as usize] & sprite_row != 0 {
+                        self.register[15] = 1; // update the F register accordingly
+                        trace!("collision detected on row {}", i);
+                    }
+                    // the pixels needs to change: apply the xor operator
+                    self.display[i as usize] ^= sprite_row;
+                }
+
+                trace!("finished drawing call.");
+            }
+
+            // multiple functions exist here, so we need another match
+            0xE000 => {
+                match opcode & 0x00FF {
+                    // conditional based on input: skip next instruction if the key is pressed
+                    0x009E => {
+                        trace!("key pressed: {:?}", self.key);
+
+                        let register_number = (opcode & 0x0F00) >> 8;
+                        let keycode = self.register[register_number as usize];
+
                         debug!(
-                            "storing bitwise operation {} from {:X} ^ {} from {:X} to {:X}",
-                            self.register[first_register as usize],
-                            first_register,
-                            self.register[second_register as usize],
-                            second_register,
-                            first_register
+                            "checking if key {:X} contained in register {:X} is pressed",
+                            keycode, register_number
+                        );
+
+                        let key_state = self.key_state(keycode);
+                        let skip = key_state == 1;
+                        self.trace_branch(opcode, keycode, key_state, skip);
+
+                        if skip {
+                            self.next_instruction();
+                            debug!("the key was pressed: skipping next instruction.");
+                        } else {
+                            debug!("the key wasn't pressed, nothing to do.");
+                        }
+                    }
+
+                    // conditional based on input: skip next instruction if the key isn't pressed
+                    0x00A1 => {
+                        trace!("key pressed: {:?}", self.key);
+
+                        let register_number = (opcode & 0x0F00) >> 8;
+                        let keycode = self.register[register_number as usize];
+
+                        debug!(
+                            "checking if key {:X} contained in register {:X} is not pressed",
+                            keycode, register_number
+                        );
+
+                        let key_state = self.key_state(keycode);
+                        let skip = key_state != 1;
+                        self.trace_branch(opcode, keycode, key_state, skip);
+
+                        if skip {
+                            self.next_instruction();
+                            debug!("the key wasn't pressed: skipping next instruction.");
+                        } else {
+                            debug!("the key was pressed, nothing to do.");
+                        }
+                    }
+
+                    _ => self.unknown_opcode(opcode)?,
+                }
+            }
+
+            // multiple functions exist here, so we need another match
+            0xF000 => {
+                match opcode & 0x00FF {
+                    // set a register to the value of the delay timer
+                    0x0007 => {
+                        let register_number = (opcode & 0x0F00) >> 8;
+                        self.register[register_number as usize] = self.delay_timer;
+                        debug!(
+                            "register {:X} set to the value of the delay timer ({})",
+                            register_number, self.delay_timer
+                        );
+                    }
+
+                    // block program execution until one key is pressed
+                    0x000A => {
+                        let register_number = (opcode & 0x0F00) >> 8;
+                        self.wait_for_key = true;
+                        self.wait_for_key_register = register_number as usize;
+                        debug!(
+                            "waiting for key; the key will be stored in register {:X}",
+                            register_number,
+                        );
+                    }
+
+                    // set the value of the delay timer
+                    0x0015 => {
+                        let register_number = (opcode & 0x0F00) >> 8;
+                        self.delay_timer = self.register[register_number as usize];
+                        debug!(
+                            "setting the delay timer to the value {} of the register {:X}",
+                            self.delay_timer, register_number
+                        );
+                    }
+
+                    // set the value of the sound timer
+                    0x0018 => {
+                        let register_number = (opcode & 0x0F00) >> 8;
+                        self.sound_timer = self.register[register_number as usize];
+                        debug!(
+                            "setting the sound timer to the value {} of the register {:X}",
+                            self.delay_timer, register_number
+                        );
+                    }
+
+                    // add the register value to the index register
+                    0x001E => {
+                        let register_number = (opcode & 0x0F00) >> 8;
+                        self.index_register = self
+                            .index_register
+                            .wrapping_add(self.register[register_number as usize] as u16);
+                        debug!(
+                            "setting index register to register {:X} value of {}",
+                            register_number, self.register[register_number as usize]
+                        );
+                    }
+
+                    // set the index register to the font sprite address of the character contained in the register
+                    0x0029 => {
+                        let character = self.register[((opcode & 0x0F00) >> 8) as usize] as u16;
+                        self.index_register = FONT_BASE as u16 + 5 * character;
+
+                        debug!(
+                            "storing in the index register the address of the character {}",
+                            character
+                        );
+                        debug!("character address: {}", self.index_register);
+                    }
+
+                    // store the decimal digits of a register in memory
+                    0x0033 => {
+                        let value = self.register[((opcode & 0x0F00) >> 8) as usize];
+                        debug!(
+                            "storing the digits of {} at address {}",
+                            value, self.index_register
+                        );
+
+                        // don't write only some of the digits
+                        let index = self.index_register as usize;
+                        if index + 3 > MEM_SIZE {
+                            return Err(Chip8Error::OutOfBounds(index));
+                        }
+
+                        self.write_mem(index, value / 100)?;
+                        self.write_mem(index + 1, value / 10 % 10)?;
+                        self.write_mem(index + 2, value % 10)?;
+                    }
+
+                    // store the registers in memory
+                    0x0055 => {
+                        let registers = (opcode & 0x0F00) >> 8;
+
+                        debug!(
+                            "storing registeries from 0 to {:X} at address {}",
+                            registers, self.index_register
+                        );
+
+                        // V0 to VX included: check the last address before writing anything
+                        let index = self.index_register as usize;
+                        if index + registers as usize >= MEM_SIZE {
+                            return Err(Chip8Error::OutOfBounds(index));
+                        }
+
+                        for i in 0..=registers as usize {
+                            self.write_mem(index + i, self.register[i])?;
+                        }
+                        self.increment_index_after_load_store(registers);
+                    }
+
+                    // fill the registers with data
+                    0x0065 => {
+                        let registers = (opcode & 0x0F00) >> 8;
+
+                        debug!(
+                            "filling registeries from 0 to {:X} of data stored at address {}",
+                            registers, self.index_register
+                        );
+
+                        // the index register can point anywhere, make sure we stay in memory.
+                        // V0 to VX included
+                        let index = self.index_register as usize;
+                        if index + registers as usize >= MEM_SIZE {
+                            return Err(Chip8Error::OutOfBounds(index));
+                        }
+
+                        for i in 0..=registers as usize {
+                            self.register[i] = self.read_mem(index + i)?;
+                            trace!("new value of {:X}: {}", i, self.register[i]);
+                        }
+                        self.increment_index_after_load_store(registers);
+                    }
+
+                    // save V0 to VX in the RPL user flags (SUPER-CHIP). There are only
+                    // 8 of them, so at most V0 to V7 are saved
+                    0x0075 => {
+                        let registers = ((opcode & 0x0F00) >> 8) as usize;
+                        let count = (registers + 1).min(RPL_SIZE);
+                        debug!(
+                            "saving registers from 0 to {:X} in the RPL flags",
+                            count - 1
                         );
-                        self.register[first_register as usize] ^=
-                            self.register[second_register as usize];
-                        trace!("result: {}", self.register[first_register as usize]);
+
+                        self.rpl[..count].copy_from_slice(&self.register[..count]);
                     }
 
-                    // add one register to another
-                    0x0004 => {
-                        let first_register = (opcode & 0x0F00) >> 8;
-                        let second_register = (opcode & 0x0F0) >> 4;
+                    // load V0 to VX from the RPL user flags (SUPER-CHIP), at most V0 to V7
+                    0x0085 => {
+                        let registers = ((opcode & 0x0F00) >> 8) as usize;
+                        let count = (registers + 1).min(RPL_SIZE);
                         debug!(
-                            "adding {} to register {:X} containing {} from register {:X}",
-                            self.register[second_register as usize],
-                            first_register,
-                            self.register[first_register as usize],
-                            second_register
+                            "loading registers from 0 to {:X} from the RPL flags",
+                            count - 1
                         );
 
-                        let mut res: u16 = self.register[first_register as usize] as u16
-                            + self.register[second_register as usize] as u16;
-                        // the result takes 9 bit: store the MSB in the carry flag register
-                        if res > 255 {
-                            debug!("{} is a 9 bit result, setting the carry flag", res);
-                            // store the first in the F register
-                            self.register[15] = 1;
-                            // discard the firt bit to have a valid 8 bit variable
-                            res &= 0b011111111;
-                            trace!("new 8 bit result (without the carry bit): {}", res);
-                        } else {
-                            debug!("result: {}", res);
-                        }
+                        self.register[..count].copy_from_slice(&self.rpl[..count]);
+                    }
+
+                    _ => self.unknown_opcode(opcode)?,
+                }
+            }
+
+            _ => self.unknown_opcode(opcode)?,
+        }
+
+        if let (Some(start), Some(profile)) = (start, self.profile.as_mut()) {
+            let family = &mut profile[(opcode >> 12) as usize];
+            family.0 += 1;
+            family.1 += start.elapsed();
+        }
+
+        // undo any change made to the frozen registers
+        for (register, &frozen) in self.register.iter_mut().zip(self.frozen_register.iter()) {
+            if let Some(value) = frozen {
+                *register = value;
+            }
+        }
+
+        if let Some((register, index_register)) = before {
+            for (i, (&old, &new)) in register.iter().zip(self.register.iter()).enumerate() {
+                if old != new {
+                    debug!("write register={:X} value={:02X}", i, new);
+                }
+            }
+            if index_register != self.index_register {
+                debug!("write index={:03X}", self.index_register);
+            }
+        }
+
+        self.trace_hash = fnv1a(self.trace_hash, &(address as u16).to_be_bytes());
+        self.trace_hash = fnv1a(self.trace_hash, &opcode.to_be_bytes());
+        self.trace_hash = fnv1a(self.trace_hash, &self.register);
+
+        Ok(())
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
+    }
+}
+
+/// the opcodes emulate() knows how to run, with a short description.
+/// X and Y stand for a register, N for a constant
+pub const OPCODES: &[(&str, &str)] = &[
+    (
+        "00CN",
+        "scroll the display down by N rows (N/2 in low resolution)",
+    ),
+    (
+        "00DN",
+        "scroll the display up by N rows (N/2 in low resolution)",
+    ),
+    ("00E0", "clear the display"),
+    ("00EE", "return from a subroutine"),
+    (
+        "00FB",
+        "scroll the display right by 4 columns (2 in low resolution)",
+    ),
+    (
+        "00FC",
+        "scroll the display left by 4 columns (2 in low resolution)",
+    ),
+    ("00FE", "switch to the 64x32 display and clear it"),
+    ("00FF", "switch to the 128x64 display and clear it"),
+    ("1NNN", "jump to NNN"),
+    ("2NNN", "call the subroutine at NNN"),
+    ("3XNN", "skip the next instruction if VX == NN"),
+    ("4XNN", "skip the next instruction if VX != NN"),
+    ("5XY0", "skip the next instruction if VX == VY"),
+    ("6XNN", "VX = NN"),
+    ("7XNN", "VX += NN"),
+    ("8XY0", "VX = VY"),
+    ("8XY1", "VX |= VY"),
+    ("8XY2", "VX &= VY"),
+    ("8XY3", "VX ^= VY"),
+    ("8XY4", "VX += VY, VF = carry"),
+    ("8XY5", "VX -= VY, VF = not borrow"),
+    ("8XY6", "VX >>= 1, VF = shifted out bit"),
+    ("8XY7", "VX = VY - VX, VF = not borrow"),
+    ("8XYE", "VX <<= 1, VF = shifted out bit"),
+    ("9XY0", "skip the next instruction if VX != VY"),
+    ("ANNN", "I = NNN"),
+    ("BNNN", "jump to NNN + V0"),
+    ("CXNN", "VX = random number & NN"),
+    (
+        "DXYN",
+        "draw a 8xN sprite at (VX;VY), or 16x16 if N = 0 in high resolution, VF = collision",
+    ),
+    ("EX9E", "skip the next instruction if the key VX is pressed"),
+    (
+        "EXA1",
+        "skip the next instruction if the key VX isn't pressed",
+    ),
+    ("FX07", "VX = delay timer"),
+    ("FX0A", "wait for a key press and store it in VX"),
+    ("FX15", "delay timer = VX"),
+    ("FX18", "sound timer = VX"),
+    ("FX1E", "I += VX"),
+    ("FX29", "I = address of the font character VX"),
+    (
+        "FX33",
+        "store the hundreds, tens and ones of VX in memory at I",
+    ),
+    ("FX55", "store V0 to VX in memory at I"),
+    ("FX65", "load V0 to VX from memory at I"),
+    ("FX75", "save V0 to VX (X <= 7) in the RPL user flags"),
+    ("FX85", "load V0 to VX (X <= 7) from the RPL user flags"),
+];
+
+// whether emulate() knows how to run an opcode
+fn is_implemented(opcode: u16) -> bool {
+    OPCODES.iter().any(|&(pattern, _)| {
+        // every hexadecimal digit of the pattern must match the opcode nibble;
+        // the other characters (X, Y, N) match anything
+        pattern
+            .chars()
+            .enumerate()
+            .all(|(i, c)| match c.to_digit(16) {
+                Some(digit) => (opcode >> (12 - 4 * i)) & 0xF == digit as u16,
+                None => true,
+            })
+    })
+}
+
+// whether an opcode is a conditional skip which reads the given register
+fn compares_register(opcode: u16, register: usize) -> bool {
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let y = ((opcode & 0x00F0) >> 4) as usize;
+
+    match opcode & 0xF000 {
+        0x3000 | 0x4000 => x == register,
+        0x5000 | 0x9000 => opcode & 0x000F == 0 && (x == register || y == register),
+        0xE000 => matches!(opcode & 0x00FF, 0x9E | 0xA1) && x == register,
+        _ => false,
+    }
+}
+
+// whether an opcode reads the given register to compute something, conditional skips excepted
+fn reads_register(opcode: u16, register: usize) -> bool {
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let y = ((opcode & 0x00F0) >> 4) as usize;
+
+    match opcode & 0xF000 {
+        0x7000 => x == register,
+        0x8000 => match opcode & 0x000F {
+            0x0 => y == register,
+            // shifts act in place
+            0x6 | 0xE => x == register,
+            0x1..=0x7 => x == register || y == register,
+            _ => false,
+        },
+        0xD000 => x == register || y == register,
+        0xF000 => match opcode & 0x00FF {
+            0x15 | 0x18 | 0x1E | 0x29 | 0x33 => x == register,
+            0x55 => register <= x,
+            0x75 => register <= x.min(RPL_SIZE - 1),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// whether an opcode stores a value of the program in the given register
+fn sets_register(opcode: u16, register: usize) -> bool {
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+
+    match opcode & 0xF000 {
+        0x6000 | 0x7000 | 0xC000 => x == register,
+        0x8000 => opcode & 0x000F <= 0x3 && x == register,
+        0xF000 => match opcode & 0x00FF {
+            0x07 | 0x0A => x == register,
+            0x65 => register <= x,
+            0x85 => register <= x.min(RPL_SIZE - 1),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// whether an opcode stores a flag in VF
+fn sets_flag(opcode: u16) -> bool {
+    match opcode & 0xF000 {
+        0x8000 => matches!(opcode & 0x000F, 0x4..=0x7 | 0xE),
+        0xD000 => true,
+        _ => false,
+    }
+}
+
+// seed of the random number generator: any time will do, as long as the seed isn't 0
+#[cfg(not(target_arch = "wasm32"))]
+fn clock_seed() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |time| time.as_nanos() as u64 | 1)
+}
+
+// there is no clock in the browser without going through javascript,
+// so the seed is fixed until set_seed() is called
+#[cfg(target_arch = "wasm32")]
+fn clock_seed() -> u64 {
+    1
+}
+
+// continue a 64 bit FNV-1a hash with some bytes
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// unpack the top left width*height pixels of a display packed as one bit per pixel
+// to display[x][y] with a value of 0 or 1 per pixel
+fn unpack(packed: &[u128; HIGH_RES_YPX], width: usize, height: usize) -> Vec<Vec<u8>> {
+    let mut display = vec![vec![0; height]; width];
+
+    for (y, &row) in packed[..height].iter().enumerate() {
+        for (x, column) in display.iter_mut().enumerate() {
+            column[y] = (row >> (HIGH_RES_XPX - 1 - x)) as u8 & 1;
+        }
+    }
+
+    display
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // write a program at the start address, ready to be emulated
+    fn load_program(chip8: &mut Chip8, program: &[u16]) {
+        for (i, &opcode) in program.iter().enumerate() {
+            chip8.memory[PC_START + 2 * i] = (opcode >> 8) as u8;
+            chip8.memory[PC_START + 2 * i + 1] = opcode as u8;
+        }
+        chip8.rom_len = 2 * program.len();
+    }
+
+    #[test]
+    fn display_is_unpacked_per_pixel() {
+        let mut chip8 = Chip8::new();
+        chip8.memory[0x300] = 0b1010_0001;
+        // V0 = 60, V1 = 31, I = 0x300, draw 1 row at (V0;V1)
+        load_program(&mut chip8, &[0x603C, 0x611F, 0xA300, 0xD011]);
+        for _ in 0..4 {
+            chip8.emulate().unwrap();
+        }
+
+        let display = chip8.display();
+        assert_eq!(display[60][31], 1);
+        assert_eq!(display[61][31], 0);
+        assert_eq!(display[62][31], 1);
+        assert_eq!(display[63][31], 0);
+        // the last set pixel is clipped by the right edge
+        let lit = display.iter().flatten().filter(|&&px| px == 1).count();
+        assert_eq!(lit, 2);
+    }
+
+    #[test]
+    fn sprites_are_not_transposed() {
+        let mut chip8 = Chip8::new();
+        // an L lying on its back, 3 pixels wide and 2 high:
+        //   #..
+        //   ###
+        chip8.memory[0x300] = 0b1000_0000;
+        chip8.memory[0x301] = 0b1110_0000;
+        // V0 = 10 (x), V1 = 4 (y), I = 0x300, draw 2 rows at (V0;V1)
+        load_program(&mut chip8, &[0x600A, 0x6104, 0xA300, 0xD012]);
+        for _ in 0..4 {
+            chip8.emulate().unwrap();
+        }
+
+        // the first index is the column (x), the second one the row (y)
+        let display = chip8.display();
+        assert_eq!(display.len(), XPX);
+        assert_eq!(display[0].len(), YPX);
+        for &(x, y) in [(10, 4), (10, 5), (11, 5), (12, 5)].iter() {
+            assert_eq!(display[x][y], 1, "({};{})", x, y);
+        }
+        for &(x, y) in [(11, 4), (12, 4), (4, 10), (5, 11)].iter() {
+            assert_eq!(display[x][y], 0, "({};{})", x, y);
+        }
+        assert_eq!(
+            chip8.display_ascii().lines().nth(4).unwrap()[9..14],
+            *".#..."
+        );
+        assert_eq!(
+            chip8.display_ascii().lines().nth(5).unwrap()[9..14],
+            *".###."
+        );
+    }
+
+    #[test]
+    fn draw_sets_collision_flag() {
+        let mut chip8 = Chip8::new();
+        chip8.memory[0x300] = 0xFF;
+        // draw the same row twice at (0;0)
+        load_program(&mut chip8, &[0xA300, 0xD001, 0xD001]);
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[15], 0);
+        assert_eq!(chip8.display()[7][0], 1);
+
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[15], 1);
+        assert!(chip8.display().iter().flatten().all(|&px| px == 0));
+    }
+
+    #[test]
+    fn switch_resolution() {
+        let mut chip8 = Chip8::new();
+        // high resolution, draw a pixel at (V0;V1), low resolution
+        load_program(&mut chip8, &[0x00FF, 0xD011, 0x00FE]);
+        chip8.register[0] = 100;
+        chip8.register[1] = 50;
+        chip8.index_register = 0x300;
+        chip8.memory[0x300] = 0x80;
+        assert_eq!(
+            (chip8.display().len(), chip8.display()[0].len()),
+            (XPX, YPX)
+        );
+
+        chip8.emulate().unwrap();
+        let display = chip8.display();
+        assert_eq!(
+            (display.len(), display[0].len()),
+            (HIGH_RES_XPX, HIGH_RES_YPX)
+        );
+        assert_eq!(
+            (chip8.width(), chip8.height()),
+            (HIGH_RES_XPX, HIGH_RES_YPX)
+        );
+
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.display()[100][50], 1);
+
+        // switching clears the screen
+        chip8.emulate().unwrap();
+        let display = chip8.display();
+        assert_eq!((display.len(), display[0].len()), (XPX, YPX));
+        assert!(display.iter().flatten().all(|&px| px == 0));
+        assert!(chip8.display.iter().all(|&row| row == 0));
+    }
+
+    // run a program drawing a pixel at (10;10) after its first instruction, and
+    // get the coordinates of the only pixel set after each instruction
+    fn track_pixel(program: &[u16]) -> Vec<Option<(usize, usize)>> {
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, program);
+        chip8.register[0] = 10;
+        chip8.index_register = 0x300;
+        chip8.memory[0x300] = 0x80;
+
+        (0..program.len())
+            .map(|_| {
+                chip8.emulate().unwrap();
+                let display = chip8.display();
+                (0..display.len())
+                    .flat_map(|x| (0..display[x].len()).map(move |y| (x, y)))
+                    .find(|&(x, y)| display[x][y] == 1)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scroll() {
+        // high resolution, draw, scroll down by 4, right, left
+        let positions = track_pixel(&[0x00FF, 0xD001, 0x00C4, 0x00FB, 0x00FC]);
+        assert_eq!(
+            positions[1..],
+            [
+                Some((10, 10)),
+                Some((10, 14)),
+                Some((14, 14)),
+                Some((10, 14))
+            ]
+        );
+
+        // low resolution scrolls by half the amount
+        let positions = track_pixel(&[0x00FE, 0xD001, 0x00C4, 0x00FB, 0x00FC]);
+        assert_eq!(
+            positions[1..],
+            [
+                Some((10, 10)),
+                Some((10, 12)),
+                Some((12, 12)),
+                Some((10, 12))
+            ]
+        );
+
+        // the pixels scrolled out of the screen are lost
+        let positions = track_pixel(&[0x00FE, 0xD001, 0x00CF, 0x00CF, 0x00CF]);
+        assert_eq!(positions[4], Some((10, 31)));
+        assert_eq!(
+            track_pixel(&[0x00FE, 0xD001, 0x00CF, 0x00CF, 0x00CF, 0x00CF])[5],
+            None
+        );
+        let mut program = vec![0x00FE, 0xD001];
+        program.extend_from_slice(&[0x00FC; 6]);
+        program.push(0x00FB);
+        assert_eq!(track_pixel(&program)[8], None);
+    }
+
+    #[test]
+    fn draw_16x16_sprite() {
+        let mut chip8 = Chip8::new();
+        // high resolution, draw a 16x16 sprite at (V0;V1) twice
+        load_program(&mut chip8, &[0x00FF, 0xD010, 0xD010]);
+        chip8.register[0] = 120;
+        chip8.register[1] = 8;
+        chip8.index_register = 0x300;
+        for byte in chip8.memory[0x300..0x320].iter_mut() {
+            *byte = 0xFF;
+        }
+
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[15], 0);
+
+        // the block is clipped by the right edge, like a 8 pixels wide sprite
+        let display = chip8.display();
+        let lit: Vec<(usize, usize)> = (0..HIGH_RES_XPX)
+            .flat_map(|x| (0..HIGH_RES_YPX).map(move |y| (x, y)))
+            .filter(|&(x, y)| display[x][y] == 1)
+            .collect();
+        assert_eq!(lit.len(), 8 * 16);
+        assert!(lit.iter().all(|&(x, y)| x >= 120 && (8..24).contains(&y)));
+
+        // drawing it again erases it, with a collision
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[15], 1);
+        assert!(chip8.display().iter().flatten().all(|&px| px == 0));
+    }
+
+    #[test]
+    fn keep_display_on_resolution_switch() {
+        for &keep_display_on_resolution_switch in [false, true].iter() {
+            let mut chip8 = Chip8::new();
+            chip8.set_quirks(Quirks {
+                keep_display_on_resolution_switch,
+                ..Quirks::default()
+            });
+            // draw a pixel at (V0;V0), high resolution, low resolution
+            load_program(&mut chip8, &[0xD001, 0x00FF, 0x00FE]);
+            chip8.register[0] = 1;
+            chip8.index_register = 0x300;
+            chip8.memory[0x300] = 0x80;
+
+            chip8.emulate().unwrap();
+            for _ in 0..2 {
+                chip8.emulate().unwrap();
+                let lit = chip8
+                    .display()
+                    .iter()
+                    .flatten()
+                    .filter(|&&px| px == 1)
+                    .count();
+                if keep_display_on_resolution_switch {
+                    assert_eq!(lit, 1);
+                    assert_eq!(chip8.display()[1][1], 1);
+                } else {
+                    assert_eq!(lit, 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clip_in_low_resolution() {
+        let mut chip8 = Chip8::new();
+        // draw a 8 pixels wide row at x = 60
+        load_program(&mut chip8, &[0xD011]);
+        chip8.register[0] = 60;
+        chip8.index_register = 0x300;
+        chip8.memory[0x300] = 0xFF;
+        chip8.emulate().unwrap();
+
+        // the pixels past the right edge don't linger out of the screen
+        assert_eq!(chip8.display[0], 0xF << (HIGH_RES_XPX - XPX));
+    }
+
+    #[test]
+    fn clear_display() {
+        let mut chip8 = Chip8::new();
+        // draw the "0" glyph of the fontset at (0;0), then clear the screen
+        load_program(&mut chip8, &[0xA000, 0xD005, 0x00E0]);
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+
+        let drawn = chip8.display_ascii();
+        let glyph: Vec<&str> = drawn.lines().take(5).map(|line| &line[..4]).collect();
+        assert_eq!(glyph, ["####", "#..#", "#..#", "#..#", "####"]);
+        assert_eq!(drawn.matches('#').count(), 14);
+
+        chip8.emulate().unwrap();
+        let blank = format!("{}\n", ".".repeat(XPX)).repeat(YPX);
+        assert_eq!(chip8.display_ascii(), blank);
+    }
+
+    // 0x200: call 0x206, 0x202: V1 = 1, 0x204: loop forever, 0x206: V0 = 5, 0x208: return
+    const SUBROUTINE_PROGRAM: [u16; 5] = [0x2206, 0x6101, 0x1204, 0x6005, 0x00EE];
+
+    #[test]
+    fn step_over_runs_the_whole_subroutine() {
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &SUBROUTINE_PROGRAM);
+
+        chip8.step_over().unwrap();
+        assert_eq!(chip8.program_counter, 0x202);
+        assert_eq!(chip8.register[0], 5);
+
+        // not a call: only one instruction is run
+        chip8.step_over().unwrap();
+        assert_eq!(chip8.program_counter, 0x204);
+        assert_eq!(chip8.register[1], 1);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut chip8 = Chip8::new();
+        // V0 += 1, skip the jump if V0 == 3, loop
+        load_program(&mut chip8, &[0x7001, 0x3003, 0x1200, 0x1206]);
+
+        chip8.set_breakpoint(0x200);
+        chip8.set_breakpoint(0x206);
+        assert_eq!(chip8.run_until_break(), Ok(0x200));
+        assert_eq!(chip8.register[0], 1);
+
+        // continuing doesn't stop on the breakpoint we are at
+        assert_eq!(chip8.run_until_break(), Ok(0x200));
+        assert_eq!(chip8.register[0], 2);
+
+        chip8.clear_breakpoint(0x200);
+        assert_eq!(chip8.run_until_break(), Ok(0x206));
+        assert_eq!(chip8.register[0], 3);
+
+        // the jump to itself never reaches another breakpoint
+        chip8.clear_breakpoint(0x206);
+        assert_eq!(
+            chip8.run_until_break(),
+            Err(Chip8Error::StepLimit(MAX_STEP_CYCLES))
+        );
+    }
+
+    #[test]
+    fn step_out_returns_to_the_caller() {
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &SUBROUTINE_PROGRAM);
+        assert_eq!(chip8.step_out(), Err(Chip8Error::NotInSubroutine));
+
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x206);
+
+        chip8.step_out().unwrap();
+        assert_eq!(chip8.program_counter, 0x202);
+        assert_eq!(chip8.register[0], 5);
+    }
+
+    #[test]
+    fn frozen_register_cant_change() {
+        let mut chip8 = Chip8::new();
+        chip8.freeze_register(0, 3);
+        load_program(&mut chip8, &[0x6009, 0x7001, 0x6009]);
+
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[0], 3);
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[0], 3);
+
+        chip8.unfreeze_register(0);
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[0], 9);
+
+        // there is no register past VF
+        chip8.freeze_register(REGISTER_NUM, 3);
+        chip8.unfreeze_register(REGISTER_NUM);
+    }
+
+    #[test]
+    fn drawn_pixels_are_reported_once() {
+        let mut chip8 = Chip8::new();
+        chip8.memory[0x300] = 0b1100_0000;
+        chip8.memory[0x301] = 0b0110_0000;
+        // draw 0b11 at (0;0), then 0b011 over it: only (2;0) gets turned on
+        load_program(&mut chip8, &[0xA300, 0xD001, 0xA301, 0xD001]);
+        for _ in 0..4 {
+            chip8.emulate().unwrap();
+        }
+
+        let drawn = chip8.take_drawn();
+        assert_eq!(drawn[0][0], 0);
+        assert_eq!(drawn[1][0], 0);
+        assert_eq!(drawn[2][0], 1);
+        assert_eq!(drawn.iter().flatten().filter(|&&px| px == 1).count(), 1);
+
+        assert!(chip8.take_drawn().iter().flatten().all(|&px| px == 0));
+    }
+
+    #[test]
+    fn scroll_up_by_half_in_low_resolution() {
+        let mut chip8 = Chip8::new();
+        chip8.memory[0x300] = 0x80;
+        // V0 = 5, draw a pixel at (0;0) and one at (0;5), then scroll up by 4 and by 1
+        load_program(
+            &mut chip8,
+            &[0xA300, 0x6005, 0xD111, 0xD101, 0x00D4, 0x00D1],
+        );
+        for _ in 0..5 {
+            chip8.emulate().unwrap();
+        }
+
+        let display = chip8.display();
+        assert_eq!(display[0][3], 1);
+        assert_eq!(display.iter().flatten().filter(|&&px| px == 1).count(), 1);
+
+        // an odd amount is rounded down
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.display(), display);
+    }
+
+    #[test]
+    fn waiting_for_key() {
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &[0xF30A, 0x6001]);
+        assert!(!chip8.is_waiting_for_key());
+
+        chip8.emulate().unwrap();
+        assert!(chip8.is_waiting_for_key());
+        chip8.emulate().unwrap();
+        assert!(chip8.is_waiting_for_key());
+
+        chip8.register_key(0xB);
+        chip8.emulate().unwrap();
+        assert!(!chip8.is_waiting_for_key());
+        assert_eq!(chip8.register[3], 0xB);
+    }
+
+    #[test]
+    fn draw_zero_height() {
+        // draw the whole fontset as a single sprite, without and with the quirk
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &[0xA000, 0xD000]);
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        assert!(chip8.display().iter().flatten().all(|&px| px == 0));
+
+        let mut chip8 = Chip8::new();
+        chip8.set_quirks(Quirks {
+            draw_zero_height: true,
+            ..Quirks::default()
+        });
+        assert!(chip8.quirks().draw_zero_height);
+        load_program(&mut chip8, &[0xA000, 0xD000]);
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        let display = chip8.display();
+        // the 16th row is the top of the "3" glyph, and the sprite stops there
+        assert_eq!(display[0][15], 1);
+        assert_eq!(display[0][16], 0);
+    }
+
+    #[test]
+    fn list_unimplemented_opcodes() {
+        let mut chip8 = Chip8::new();
+        load_program(
+            &mut chip8,
+            &[0x00E0, 0x0230, 0x6001, 0x8018, 0xF0FF, 0xF065],
+        );
+
+        assert_eq!(
+            chip8.unimplemented_opcodes(),
+            [(0x202, 0x0230), (0x206, 0x8018), (0x208, 0xF0FF)]
+        );
+    }
+
+    #[test]
+    fn hires_program() {
+        let mut chip8 = Chip8::new();
+        // jump over the interpreter patch, draw a pixel at (V0;V0), clear the screen
+        let mut rom = vec![0; 0x64];
+        rom[..2].copy_from_slice(&[0x12, 0x60]);
+        rom[0x60..].copy_from_slice(&[0xD0, 0x01, 0x02, 0x30]);
+        chip8.load_bytes(&rom).unwrap();
+        assert_eq!((chip8.width(), chip8.height()), (XPX, HIGH_RES_YPX));
+        // the patch is data, but 0x0230 is known to clear the screen
+        assert!(chip8
+            .unimplemented_opcodes()
+            .iter()
+            .all(|&(_, opcode)| opcode != 0x0230));
+
+        chip8.register[0] = 40;
+        chip8.index_register = 0x300;
+        chip8.memory[0x300] = 0x80;
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        let display = chip8.display();
+        assert_eq!((display.len(), display[0].len()), (XPX, HIGH_RES_YPX));
+        assert_eq!(display[40][40], 1);
+
+        chip8.emulate().unwrap();
+        assert!(chip8.display().iter().flatten().all(|&px| px == 0));
+
+        // other programs keep the usual screen
+        chip8.load_bytes(&[0x00, 0xE0]).unwrap();
+        assert_eq!((chip8.width(), chip8.height()), (XPX, YPX));
+    }
+
+    #[test]
+    fn store_and_load_registers() {
+        let mut chip8 = Chip8::new();
+        // store V0 to V3, load them back
+        load_program(&mut chip8, &[0xF355, 0xF365]);
+
+        chip8.register[..5].copy_from_slice(&[1, 2, 3, 4, 5]);
+        chip8.index_register = 0x300;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.memory[0x300..0x305], [1, 2, 3, 4, 0]);
+
+        chip8.register = [0; REGISTER_NUM];
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[..5], [1, 2, 3, 4, 0]);
+    }
+
+    #[test]
+    fn store_decimal_digits() {
+        let mut chip8 = Chip8::new();
+        // store the digits of V0 at I, twice
+        load_program(&mut chip8, &[0xF033, 0xF033]);
+
+        chip8.register[0] = 254;
+        chip8.index_register = 0x300;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.memory[0x300..0x303], [2, 5, 4]);
+
+        chip8.index_register = (MEM_SIZE - 2) as u16;
+        assert_eq!(chip8.emulate(), Err(Chip8Error::OutOfBounds(MEM_SIZE - 2)));
+        assert_eq!(chip8.memory[MEM_SIZE - 2..], [0, 0]);
+    }
+
+    #[test]
+    fn random_number() {
+        let mut chip8 = Chip8::new();
+        // V0 = random & 0x0F, V1 = random & 0
+        load_program(&mut chip8, &[0xC00F, 0xC100]);
+        chip8.register[1] = 0xFF;
+
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        assert!(chip8.register[0] <= 0x0F);
+        assert_eq!(chip8.register[1], 0);
+
+        // the same state gives the same numbers
+        let mut other = Chip8::new();
+        chip8.rng = 42;
+        other.rng = 42;
+        assert_eq!(chip8.random(), other.random());
+        assert_ne!(chip8.rng, 42);
+    }
+
+    #[test]
+    fn jump_with_offset() {
+        let mut chip8 = Chip8::new();
+        // jump to 0x300 + V0, jump to 0xFFF + V0
+        load_program(&mut chip8, &[0xB300]);
+        chip8.memory[0x310] = 0xBF;
+        chip8.memory[0x311] = 0xFF;
+
+        chip8.register[0] = 0x10;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x310);
+
+        // wraps around the memory
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x00F);
+    }
+
+    #[test]
+    fn subtraction_with_borrow() {
+        let mut chip8 = Chip8::new();
+        // V0 -= V1
+        load_program(&mut chip8, &[0x8015]);
+
+        chip8.register[0] = 3;
+        chip8.register[1] = 5;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[0], 254);
+        assert_eq!(chip8.register[15], 0);
+
+        // VF -= V1: the flag wins over the result
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &[0x6F05, 0x6103, 0x8F15]);
+        for _ in 0..3 {
+            chip8.emulate().unwrap();
+        }
+        assert_eq!(chip8.register[15], 1);
+    }
+
+    #[test]
+    fn reverse_subtraction() {
+        let mut chip8 = Chip8::new();
+        // V0 = V1 - V0, twice
+        load_program(&mut chip8, &[0x8017, 0x8017]);
+
+        chip8.register[0] = 3;
+        chip8.register[1] = 5;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[0], 2);
+        assert_eq!(chip8.register[15], 1);
+
+        chip8.register[0] = 6;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[0], 255);
+        assert_eq!(chip8.register[15], 0);
+
+        // VF = V1 - VF: the flag wins over the result
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &[0x6F05, 0x6103, 0x8F17]);
+        for _ in 0..3 {
+            chip8.emulate().unwrap();
+        }
+        assert_eq!(chip8.register[15], 0);
+    }
+
+    #[test]
+    fn skip_if_registers_equal() {
+        let mut chip8 = Chip8::new();
+        // skip if V1 == V2, twice
+        load_program(&mut chip8, &[0x5120, 0x0000, 0x5120]);
+
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x204);
+
+        chip8.program_counter = 0x200;
+        chip8.register[2] = 1;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x202);
+
+        // 5XY1 isn't an instruction
+        load_program(&mut chip8, &[0x5121]);
+        chip8.program_counter = 0x200;
+        assert_eq!(chip8.emulate(), Err(Chip8Error::UnknownOpcode(0x5121)));
+    }
+
+    #[test]
+    fn skip_if_registers_differ() {
+        let mut chip8 = Chip8::new();
+        // skip if V0 != V1, twice
+        load_program(&mut chip8, &[0x9010, 0x0000, 0x9010]);
+
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x202);
+
+        chip8.program_counter = 0x200;
+        chip8.register[1] = 1;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x204);
+    }
+
+    #[test]
+    fn diverging_runs_have_different_trace_hashes() {
+        // V0 += 1, V1 = V0, loop
+        let program = [0x7001, 0x8100, 0x1200];
+        let run = |register: u8| {
+            let mut chip8 = Chip8::new();
+            load_program(&mut chip8, &program);
+            chip8.register[0] = register;
+            for _ in 0..30 {
+                chip8.emulate().unwrap();
+            }
+            chip8.trace_hash()
+        };
+
+        assert_eq!(run(0), run(0));
+        assert_ne!(run(0), run(1));
+    }
+
+    #[test]
+    fn skip_on_key() {
+        let mut chip8 = Chip8::new();
+        chip8.register_key(5);
+        // skip if the key V0 is pressed, skip if it isn't
+        load_program(&mut chip8, &[0xE09E, 0x0000, 0xE0A1]);
+
+        chip8.register[0] = 5;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x204);
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x206);
+
+        // a key past the keypad is never pressed
+        chip8.register[0] = 0xFF;
+        chip8.program_counter = 0x200;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x202);
+        chip8.program_counter = 0x204;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x208);
+    }
+
+    #[test]
+    fn keys_as_bits() {
+        let mut chip8 = Chip8::new();
+        chip8.register_key(1);
+        chip8.register_key(0xF);
+        assert_eq!(chip8.keys(), 0x8002);
+
+        chip8.set_keys(0x0011);
+        assert_eq!(chip8.key[0], 1);
+        assert_eq!(chip8.key[1], 0);
+        assert_eq!(chip8.keys(), 0x0011);
+    }
+
+    #[test]
+    fn vf_reuse() {
+        let mut chip8 = Chip8::new();
+
+        // VF = 5, V0 += V1 (carry in VF), VF += 1
+        assert!(!chip8.check_vf_reuse(0x6F05));
+        assert!(!chip8.check_vf_reuse(0x8014));
+        assert!(chip8.check_vf_reuse(0x7F01));
+
+        // VF = 5, V0 += V1 (carry in VF), skip if there is a carry, VF += 1
+        assert!(!chip8.check_vf_reuse(0x6F05));
+        assert!(!chip8.check_vf_reuse(0x8014));
+        assert!(!chip8.check_vf_reuse(0x3F01));
+        assert!(!chip8.check_vf_reuse(0x7F01));
+    }
+
+    #[test]
+    fn trap_on_jump_to_itself() {
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &[0x1200]);
+
+        assert!(chip8.emulate().is_ok());
+        chip8.set_trap_halt(true);
+        assert_eq!(chip8.emulate(), Err(Chip8Error::Halt(0x200)));
+        assert_eq!(chip8.program_counter, 0x200);
+    }
+
+    #[test]
+    fn costly_draw() {
+        let mut chip8 = Chip8::new();
+        chip8.set_draw_cost(3);
+        // draw, then V0 = 1
+        load_program(&mut chip8, &[0xD001, 0x6001]);
+
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 0x202);
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[0], 1);
+    }
+
+    #[test]
+    fn advance_by_elapsed_time() {
+        let mut chip8 = Chip8::new();
+        // V0 += 1, forever
+        load_program(&mut chip8, &[0x7001, 0x1200]);
+
+        chip8.advance(Duration::from_secs(1)).unwrap();
+        assert_eq!(chip8.register[0], 30);
+
+        // the time of both calls adds up to a single instruction
+        chip8.advance(Duration::from_millis(10)).unwrap();
+        assert_eq!(chip8.program_counter, 0x200);
+        chip8.advance(Duration::from_millis(10)).unwrap();
+        assert_eq!(chip8.program_counter, 0x202);
+    }
+
+    #[test]
+    fn advance_at_a_custom_clock() {
+        let mut chip8 = Chip8::new();
+        chip8.set_clock_hz(600);
+        // delay timer = 60, then V0 += 1, forever
+        load_program(&mut chip8, &[0x603C, 0xF015, 0x7001, 0x1204]);
+
+        chip8.advance(Duration::from_millis(100)).unwrap();
+        // 60 instructions, the first 2 of them outside the loop
+        assert_eq!(chip8.register[0], 60 + (60 - 2) / 2);
+        // while the timer only counted down for 1/10s
+        assert_eq!(chip8.delay_timer, 60 - 6);
+    }
+
+    #[test]
+    fn advance_at_the_fastest_clock() {
+        let mut chip8 = Chip8::new();
+        // a faster clock would make an instruction take no time at all
+        chip8.set_clock_hz(u32::MAX);
+        assert_eq!(chip8.clock_hz(), MAX_CLOCK_HZ);
+        // V0 += 1, forever
+        load_program(&mut chip8, &[0x7001, 0x1200]);
+
+        chip8.advance(Duration::from_nanos(4)).unwrap();
+        assert_eq!(chip8.register[0], 2);
+    }
+
+    #[test]
+    fn compat_report() {
+        let mut chip8 = Chip8::new();
+        // unknown opcode, return without a subroutine, jump to itself
+        load_program(&mut chip8, &[0xF0FF, 0x00EE, 0x1204]);
+
+        let report = chip8.compat_report(100);
+        assert_eq!(report.cycles, 3);
+        assert_eq!(report.unknown_opcodes.get(&0xF0FF), Some(&1));
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.halted_at, Some(0x204));
+        assert!(!report.waiting_for_key);
+    }
+
+    #[test]
+    fn load_gzip_program() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0x60, 0x2A, 0x12, 0x02]).unwrap();
+        let file_path = std::env::temp_dir().join("rust_chip8_test_program.ch8.gz");
+        std::fs::write(&file_path, encoder.finish().unwrap()).unwrap();
+
+        let mut chip8 = Chip8::new();
+        chip8.load(file_path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&file_path).unwrap();
+
+        assert_eq!(
+            chip8.memory[PC_START..PC_START + 4],
+            [0x60, 0x2A, 0x12, 0x02]
+        );
+        assert_eq!(chip8.rom_len, 4);
+    }
+
+    #[test]
+    fn load_program_starting_like_gzip() {
+        // 1F8B is a valid opcode (jump to 0xF8B), not only the gzip magic bytes
+        let program = [0x1F, 0x8B, 0x60, 0x2A];
+        let file_path = std::env::temp_dir().join("rust_chip8_test_gzip_magic.ch8");
+        std::fs::write(&file_path, program).unwrap();
+
+        let mut chip8 = Chip8::new();
+        chip8.load(file_path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&file_path).unwrap();
+
+        assert_eq!(chip8.memory[PC_START..PC_START + 4], program);
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let mut chip8 = Chip8::new();
+        // V0 = 5, call 0x208, V0 += 1, loop, delay timer = V0, draw, return
+        load_program(
+            &mut chip8,
+            &[0x6005, 0x2208, 0x7001, 0x1206, 0xF015, 0xD005, 0x00EE],
+        );
+        for _ in 0..4 {
+            chip8.emulate().unwrap();
+        }
+
+        let state = chip8.save_state();
+        let display = chip8.display;
+        for _ in 0..4 {
+            chip8.emulate().unwrap();
+        }
+        assert_ne!(chip8.save_state(), state);
+
+        chip8.load_state(&state).unwrap();
+        assert_eq!(chip8.save_state(), state);
+        assert_eq!(chip8.program_counter, 0x20C);
+        assert_eq!(chip8.register[0], 5);
+        assert_eq!(chip8.delay_timer, 5);
+        assert_eq!(chip8.stack_pointer, 1);
+        assert_eq!(chip8.display, display);
+
+        // other versions and corrupted states are rejected
+        let mut other_version = state.clone();
+        other_version[STATE_MAGIC.len()] += 1;
+        assert_eq!(
+            chip8.load_state(&other_version),
+            Err(Chip8Error::InvalidState(format!(
+                "unsupported version {}",
+                STATE_VERSION + 1
+            )))
+        );
+        assert!(chip8.load_state(&state[1..]).is_err());
+        assert!(chip8.load_state(&state[..STATE_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn reset_to_a_freshly_loaded_program() {
+        // V0 = 5, call 0x206, delay timer = V0, draw, loop
+        let program = [0x6005, 0x2206, 0x1204, 0xF015, 0xD005, 0x1204];
+        let mut fresh = Chip8::new();
+        load_program(&mut fresh, &program);
+
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &program);
+        chip8.index_register = 0x50;
+        chip8.register_key(3);
+        for _ in 0..5 {
+            chip8.emulate().unwrap();
+        }
+        chip8.reset();
+
+        assert_eq!(chip8.memory[..], fresh.memory[..]);
+        assert_eq!(chip8.register, fresh.register);
+        assert_eq!(chip8.program_counter, fresh.program_counter);
+        assert_eq!(chip8.index_register, fresh.index_register);
+        assert_eq!(chip8.display, fresh.display);
+        assert_eq!(chip8.delay_timer, fresh.delay_timer);
+        assert_eq!(chip8.stack, fresh.stack);
+        assert_eq!(chip8.stack_pointer, fresh.stack_pointer);
+        assert_eq!(chip8.key, fresh.key);
+        assert_eq!(chip8.trace_hash, fresh.trace_hash);
+    }
+
+    #[test]
+    fn timers_only_count_down_on_ticks() {
+        let mut chip8 = Chip8::new();
+        // delay timer = V0, sound timer = V0, loop
+        load_program(&mut chip8, &[0xF015, 0xF018, 0x1204]);
+        chip8.register[0] = 10;
+
+        for _ in 0..100 {
+            chip8.emulate().unwrap();
+        }
+        assert_eq!((chip8.delay_timer, chip8.sound_timer), (10, 10));
+
+        chip8.tick_timers();
+        assert_eq!((chip8.delay_timer, chip8.sound_timer), (9, 9));
+
+        // the beep stops with the sound timer
+        assert!(chip8.is_beeping());
+        for _ in 0..9 {
+            chip8.tick_timers();
+        }
+        assert!(!chip8.is_beeping());
+    }
+
+    #[test]
+    fn step_runs_a_single_instruction() {
+        let mut chip8 = Chip8::new();
+        // V0 = 5, delay timer = V0, V1 = 7
+        load_program(&mut chip8, &[0x6005, 0xF015, 0x6107]);
+
+        chip8.step().unwrap();
+        assert_eq!(chip8.program_counter, 0x202);
+        assert_eq!(chip8.register[..2], [5, 0]);
+
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.program_counter, 0x206);
+        assert_eq!(chip8.register[..2], [5, 7]);
+        assert_eq!(chip8.delay_timer, 5);
+    }
+
+    #[test]
+    fn debug_state() {
+        let mut chip8 = Chip8::new();
+        // V0 = 5, I = 2EA, call 0x300, 0x300: delay timer = V0
+        load_program(&mut chip8, &[0x6005, 0xA2EA, 0x2300]);
+        chip8.memory[0x300..0x302].copy_from_slice(&[0xF0, 0x15]);
+        chip8.register_key(0xA);
 
-                        self.register[first_register as usize] = res as u8;
-                    }
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
 
-                    // substract the first register by the second register
-                    0x0005 => {
-                        let first_register = (opcode & 0x0F00) >> 8;
-                        let second_register = (opcode & 0x0F0) >> 4;
+        let state = chip8.debug_state();
+        assert_eq!(state.registers[0], 5);
+        assert_eq!(state.program_counter, 0x302);
+        assert_eq!(state.index_register, 0x2EA);
+        assert_eq!(state.stack[..state.stack_pointer], [0x206]);
+        assert_eq!((state.delay_timer, state.sound_timer), (5, 0));
+        assert_eq!(state.keys[0xA], 1);
+
+        let dump = state.to_string();
+        assert!(dump.starts_with("PC=302 I=2EA SP=1 DT=05 ST=00\nV0=05 V1=00"));
+        assert!(dump.ends_with("VF=00\nstack: 206\nkeys: A"));
+    }
 
-                        // set the borrow flag if the second register is greater than the first one
-                        if self.register[second_register as usize]
-                            > self.register[first_register as usize]
-                        {
-                            self.register[15] = 0; // it's a bit confusing: 0 means borrowing
-                        } else {
-                            self.register[15] = 1; //
-                        }
+    #[test]
+    fn rpl_flags() {
+        let mut chip8 = Chip8::new();
+        // save V0 to V2, clear V0, load V0 to V1, save V0 to VF
+        load_program(&mut chip8, &[0xF275, 0x6000, 0xF185, 0xFF75]);
+        chip8.register[..3].copy_from_slice(&[1, 2, 3]);
+        chip8.register[15] = 0x42;
 
-                        self.register[first_register as usize] -=
-                            self.register[second_register as usize];
-                    }
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.rpl(), [1, 2, 3, 0, 0, 0, 0, 0]);
 
-                    // stores LSB in register F and shift the register to the right
-                    0x0006 => {
-                        let register_number = (opcode & 0x0F00) >> 8;
-                        debug!(
-                            "shifting right by one {} in {:X}",
-                            self.register[register_number as usize], register_number
-                        );
-                        // store the lsb in the F register
-                        self.register[15] = self.register[register_number as usize] & 1;
-                        debug!("lsb {} stored in register F", self.register[15]);
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[..3], [1, 2, 3]);
 
-                        // store the shift back in the register
-                        self.register[register_number as usize] >>= 1;
-                        debug!("result: {}", self.register[register_number as usize]);
-                    }
-                    // stores MSB in register F and shift the register to the left
-                    0x000E => {
-                        let register_number = (opcode & 0x0F00) >> 8;
-                        debug!(
-                            "shifting left by one {} in {:X}",
-                            self.register[register_number as usize], register_number
-                        );
-                        // store the msb in the F register
-                        self.register[15] =
-                            (self.register[register_number as usize] & 0b10000000) >> 7;
-                        debug!("msb {} stored in register F", self.register[15]);
+        // only V0 to V7 fit in the flags
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.rpl(), [1, 2, 3, 0, 0, 0, 0, 0]);
 
-                        // store the shift back in the register
-                        self.register[register_number as usize] <<= 1;
-                        debug!("result: {}", self.register[register_number as usize]);
-                    }
+        // the flags outlive the program
+        chip8.reset();
+        assert_eq!(chip8.rpl(), [1, 2, 3, 0, 0, 0, 0, 0]);
+    }
 
-                    _ => warn!("warning: ran into unknown opcode: {:X}", opcode),
-                }
+    #[test]
+    fn quirks_presets() {
+        assert_eq!(
+            Quirks::preset("chip8").map(|quirks| quirks.shift_uses_vy),
+            Some(true)
+        );
+        assert_eq!(
+            Quirks::preset("schip").map(|quirks| quirks.shift_uses_vy),
+            Some(false)
+        );
+        assert!(Quirks::preset("xochip").is_none());
+
+        // every preset differs from the others
+        for (i, (_, quirks)) in QUIRKS_PRESETS.iter().enumerate() {
+            assert!(QUIRKS_PRESETS[..i].iter().all(|(_, other)| other != quirks));
+        }
+    }
+
+    #[test]
+    fn vf_reset_on_logic() {
+        for &vf_reset_on_logic in [false, true].iter() {
+            let mut chip8 = Chip8::new();
+            chip8.set_quirks(Quirks {
+                vf_reset_on_logic,
+                ..Quirks::default()
+            });
+            // V0 |= V1, V0 &= V1, V0 ^= V1, each after VF = 0x42
+            load_program(
+                &mut chip8,
+                &[0x6F42, 0x8011, 0x6F42, 0x8012, 0x6F42, 0x8013],
+            );
+            chip8.register[0] = 0b1100;
+            chip8.register[1] = 0b1010;
+
+            let mut results = Vec::new();
+            for _ in 0..3 {
+                chip8.emulate().unwrap();
+                chip8.emulate().unwrap();
+                results.push((chip8.register[0], chip8.register[15]));
             }
 
-            // set the value of the index register
-            0xA000 => {
-                self.index_register = opcode & 0x0FFF;
-                debug!("setting index register to {}", self.index_register);
+            let vf = if vf_reset_on_logic { 0 } else { 0x42 };
+            assert_eq!(results, [(0b1110, vf), (0b1010, vf), (0b0000, vf)]);
+        }
+    }
+
+    #[test]
+    fn shift_uses_vy() {
+        // (quirk, shift) -> (VX, VF) with VX = 0b0110_0000 and VY = 0b1000_0011
+        let cases = [
+            (false, 0x8016, (0b0011_0000, 0)),
+            (false, 0x801E, (0b1100_0000, 0)),
+            (true, 0x8016, (0b0100_0001, 1)),
+            (true, 0x801E, (0b0000_0110, 1)),
+        ];
+
+        for &(shift_uses_vy, opcode, result) in cases.iter() {
+            let mut chip8 = Chip8::new();
+            chip8.set_quirks(Quirks {
+                shift_uses_vy,
+                ..Quirks::default()
+            });
+            load_program(&mut chip8, &[opcode]);
+            chip8.register[0] = 0b0110_0000;
+            chip8.register[1] = 0b1000_0011;
+
+            chip8.emulate().unwrap();
+            assert_eq!((chip8.register[0], chip8.register[15]), result);
+            // VY is only read
+            assert_eq!(chip8.register[1], 0b1000_0011);
+        }
+    }
+
+    #[test]
+    fn increment_index_on_load_store() {
+        for &increment_index_on_load_store in [false, true].iter() {
+            let mut chip8 = Chip8::new();
+            chip8.set_quirks(Quirks {
+                increment_index_on_load_store,
+                ..Quirks::default()
+            });
+            // store V0 to V2, load V0 to V3
+            load_program(&mut chip8, &[0xF255, 0xF365]);
+            chip8.index_register = 0x300;
+
+            chip8.emulate().unwrap();
+            let after_store = chip8.index_register;
+            chip8.emulate().unwrap();
+            let after_load = chip8.index_register;
+
+            if increment_index_on_load_store {
+                assert_eq!((after_store, after_load), (0x303, 0x307));
+            } else {
+                assert_eq!((after_store, after_load), (0x300, 0x300));
             }
+        }
+    }
 
-            // draw to the screen
-            0xD000 => {
-                // clear the F register; it's going to be used for collision detection.
-                self.register[15] = 0;
+    #[test]
+    fn headless_run() {
+        let mut chip8 = Chip8::new();
+        // draw the glyphs 0 to 3 side by side, each one a row lower, then stop
+        load_program(
+            &mut chip8,
+            &[
+                0x6000, 0x6100, // V0 = 0, V1 = 0
+                0xF029, 0xD105, // I = glyph V0, draw it at (V1;V0)
+                0x7001, 0x7105, // V0 += 1, V1 += 5
+                0x3004, 0x1204, // loop until V0 == 4
+                0x1210, // stop
+            ],
+        );
 
-                // get the x coordinate of where to draw on the display
-                let x = self.register[((opcode & 0x0F00) >> 8) as usize] as u16;
-                // get the y coordinate
-                let y = self.register[((opcode & 0x00F0) >> 4) as usize] as u16;
-                debug!("starting drawing operation at ({};{})", x, y);
+        chip8.run_cycles(100).unwrap();
+        assert_eq!(chip8.program_counter, 0x210);
+        assert_eq!(chip8.display_hash(), 0x9CFD_C9BE_85A3_98E3);
+
+        // the hash follows the screen
+        let mut blank = Chip8::new();
+        assert_ne!(blank.display_hash(), chip8.display_hash());
+        blank.load_state(&chip8.save_state()).unwrap();
+        assert_eq!(blank.display_hash(), chip8.display_hash());
+        chip8.reset();
+        assert_eq!(chip8.display_hash(), Chip8::new().display_hash());
+    }
 
-                // sprite height
-                let height = opcode & 0x000F;
-                trace!("height of the drawing: {}", height);
+    #[test]
+    fn load_bytes() {
+        let mut chip8 = Chip8::new();
+        chip8.load_bytes(&[0x00, 0xE0, 0x12, 0x00]).unwrap();
+        assert_eq!(
+            chip8.memory[PC_START..PC_START + 5],
+            [0x00, 0xE0, 0x12, 0x00, 0x00]
+        );
+        assert_eq!(chip8.rom_len(), 4);
 
-                for i in y..y + height {
-                    // get the pixels data from the memory, using the index register
-                    // make sure we're not drawing out of the screen
-                    if i >= YPX as u16 {
-                        trace!("attempt to draw out of the screen catched !");
-                        continue;
-                    }
+        assert_eq!(
+            chip8.load_bytes(&[0; MEM_SIZE]),
+            Err(Chip8Error::RomTooLarge)
+        );
+        assert_eq!(chip8.rom_len(), 4);
+
+        // the program fills the memory from PC_START up to the last byte
+        chip8.load_bytes(&[0x2A; MEM_SIZE - PC_START]).unwrap();
+        assert_eq!(chip8.memory[MEM_SIZE - 1], 0x2A);
+        assert_eq!(chip8.rom_len(), 3584);
+        assert_eq!(
+            chip8.load_bytes(&[0; MEM_SIZE - PC_START + 1]),
+            Err(Chip8Error::RomTooLarge)
+        );
+        assert_eq!(chip8.rom_len(), 3584);
+    }
 
-                    let px_row = self.memory[(self.index_register + (i - y) as u16) as usize];
+    #[test]
+    fn errors_are_typed() {
+        let mut chip8 = Chip8::new();
+        // one byte too many for the memory left to the program
+        let too_large = vec![0; MEM_SIZE - PC_START + 1];
+        assert_eq!(chip8.load_bytes(&too_large), Err(Chip8Error::RomTooLarge));
+        assert!(matches!(
+            chip8.load("roms/does-not-exist.ch8"),
+            Err(Chip8Error::Io(_))
+        ));
+
+        // waiting for a key at 0x200
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &[0xF00A]);
+        assert_eq!(
+            chip8.run_until_break(),
+            Err(Chip8Error::WaitingForKey(0x200))
+        );
 
-                    for j in x..x + 8 {
-                        // make sure we're not drawing out of the screen
-                        if j >= XPX as u16 {
-                            trace!("attempt to draw out of the screen catched !");
-                            continue;
-                        }
+        assert_eq!(
+            Chip8Error::OutOfBounds(0xFFE).to_string(),
+            "attempt to access out of memory from address FFE !"
+        );
+        assert_eq!(
+            Chip8Error::UnknownOpcode(0x5121).to_string(),
+            "unknown opcode 5121 !"
+        );
+    }
 
-                        // evaluate the value of the pixel
-                        // 0x80 >> (j - x) will get evaluated like that:
-                        // 10000000
-                        // 01000000
-                        // 00100000 ...
-                        // with the and operator, we can ensure the pixel is set if the resulting
-                        // value is different from 0
-                        if px_row & (0x80 >> (j - x)) != 0 {
-                            // collision detected
-                            if self.display[j as usize][i as usize] == 1 {
-                                self.register[15] = 1; // update the F register accordingly
-                                trace!("collision detected at ({};{})", i, j);
-                            }
-                            // the pixel needs to change apply the xor operator
-                            self.display[j as usize][i as usize] ^= 1;
-                        }
-                    }
-                }
+    #[test]
+    fn display_as_rgba() {
+        let mut chip8 = Chip8::new();
+        chip8.display[1] = 1 << (HIGH_RES_XPX - 2);
+
+        let rgba = chip8.display_rgba();
+        assert_eq!(rgba.len(), 4 * XPX * YPX);
+        // pixel (1;1)
+        let px = 4 * (XPX + 1);
+        assert_eq!(
+            rgba[px - 4..px + 8],
+            [0, 0, 0, 255, 255, 255, 255, 255, 0, 0, 0, 255]
+        );
+        assert_eq!(
+            rgba.iter().filter(|&&byte| byte == 255).count(),
+            XPX * YPX + 3
+        );
+    }
 
-                trace!("finished drawing call.");
-            }
+    #[test]
+    fn dirty_after_draw() {
+        let mut chip8 = Chip8::new();
+        // draw, V0 = 1, clear the screen
+        load_program(&mut chip8, &[0xD001, 0x6001, 0x00E0]);
 
-            // multiple functions exist here, so we need another match
-            0xE000 => {
-                match opcode & 0x00FF {
-                    // conditional based on input: skip next instruction if the key is pressed
-                    0x009E => {
-                        trace!("key pressed: {:?}", self.key);
+        // the first frame is always rendered
+        assert!(chip8.take_dirty());
+        assert!(!chip8.take_dirty());
 
-                        let register_number = (opcode & 0x0F00) >> 8;
-                        let keycode = self.register[register_number as usize];
+        chip8.emulate().unwrap();
+        assert!(chip8.take_dirty());
+        assert!(!chip8.take_dirty());
 
-                        debug!(
-                            "checking if key {:X} contained in register {:X} is pressed",
-                            keycode, register_number
-                        );
+        chip8.emulate().unwrap();
+        assert!(!chip8.take_dirty());
 
-                        if self.key[keycode as usize] == 1 {
-                            self.program_counter += 2;
-                            debug!("the key was pressed: skipping next instruction.");
-                        } else {
-                            debug!("the key wasn't pressed, nothing to do.");
-                        }
-                    }
+        chip8.emulate().unwrap();
+        assert!(chip8.take_dirty());
+    }
 
-                    // conditional based on input: skip next instruction if the key isn't pressed
-                    0x00A1 => {
-                        trace!("key pressed: {:?}", self.key);
+    #[test]
+    fn nested_subroutines() {
+        let mut chip8 = Chip8::new();
+        // 0x200: call 0x300, 0x300: call 0x400, 0x400: call 0x500, then return 3 times
+        load_program(&mut chip8, &[0x2300, 0x1202]);
+        for &(address, opcode) in [
+            (0x300, 0x2400),
+            (0x302, 0x00EE),
+            (0x400, 0x2500),
+            (0x402, 0x00EE),
+            (0x500, 0x00EE),
+        ]
+        .iter()
+        {
+            chip8.memory[address] = (opcode >> 8) as u8;
+            chip8.memory[address + 1] = opcode as u8;
+        }
 
-                        let register_number = (opcode & 0x0F00) >> 8;
-                        let keycode = self.register[register_number as usize];
+        for &address in [0x300, 0x400, 0x500, 0x402, 0x302, 0x202].iter() {
+            chip8.emulate().unwrap();
+            assert_eq!(chip8.program_counter, address);
+        }
+        assert_eq!(chip8.stack_pointer, 0);
+    }
 
-                        debug!(
-                            "checking if key {:X} contained in register {:X} is not pressed",
-                            keycode, register_number
-                        );
+    #[test]
+    fn stack_overflow() {
+        let mut chip8 = Chip8::new();
+        // call itself forever
+        load_program(&mut chip8, &[0x2200]);
 
-                        if self.key[keycode as usize] != 1 {
-                            self.program_counter += 2;
-                            debug!("the key wasn't pressed: skipping next instruction.");
-                        } else {
-                            debug!("the key was pressed, nothing to do.");
-                        }
-                    }
+        for _ in 0..STACK_LAYERS {
+            chip8.emulate().unwrap();
+        }
+        assert_eq!(chip8.emulate(), Err(Chip8Error::StackOverflow));
+        assert_eq!(chip8.stack_pointer, STACK_LAYERS);
+    }
 
-                    _ => warn!("warning: ran into unknown opcode: {:X}", opcode),
-                }
-            }
+    #[test]
+    fn unknown_opcode() {
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &[0xF0FF, 0x5121, 0x6001]);
 
-            // multiple functions exist here, so we need another match
-            0xF000 => {
-                match opcode & 0x00FF {
-                    // set a register to the value of the delay timer
-                    0x0007 => {
-                        let register_number = (opcode & 0x0F00) >> 8;
-                        self.register[register_number as usize] = self.delay_timer;
-                        debug!(
-                            "register {:X} set to the value of the delay timer ({})",
-                            register_number, self.delay_timer
-                        );
-                    }
+        assert_eq!(chip8.emulate(), Err(Chip8Error::UnknownOpcode(0xF0FF)));
+        assert_eq!(chip8.program_counter, 0x202);
 
-                    // block program execution until one key is pressed
-                    0x000A => {
-                        let register_number = (opcode & 0x0F00) >> 8;
-                        self.wait_for_key = true;
-                        self.wait_for_key_register = register_number as usize;
-                        debug!(
-                            "waiting for key; the key will be stored in register {:X}",
-                            register_number,
-                        );
-                    }
+        // skipped with a warning
+        chip8.set_ignore_unknown(true);
+        assert!(chip8.emulate().is_ok());
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[0], 1);
+    }
 
-                    // set the value of the delay timer
-                    0x0015 => {
-                        let register_number = (opcode & 0x0F00) >> 8;
-                        self.delay_timer = self.register[register_number as usize];
-                        debug!(
-                            "setting the delay timer to the value {} of the register {:X}",
-                            self.delay_timer, register_number
-                        );
-                    }
+    #[test]
+    fn return_with_empty_stack() {
+        let mut chip8 = Chip8::new();
+        load_program(&mut chip8, &[0x00EE, 0x00EE]);
 
-                    // set the value of the sound timer
-                    0x0018 => {
-                        let register_number = (opcode & 0x0F00) >> 8;
-                        self.sound_timer = self.register[register_number as usize];
-                        debug!(
-                            "setting the sound timer to the value {} of the register {:X}",
-                            self.delay_timer, register_number
-                        );
-                    }
+        // skipped, instead of jumping to the fontset
+        assert!(chip8.emulate().is_ok());
+        assert_eq!(chip8.program_counter, 0x202);
 
-                    // add the register value to the index register
-                    0x001E => {
-                        let register_number = (opcode & 0x0F00) >> 8;
-                        self.index_register += self.register[register_number as usize] as u16;
-                        debug!(
-                            "setting index register to register {:X} value of {}",
-                            register_number, self.register[register_number as usize]
-                        );
-                    }
+        chip8.set_strict(true);
+        assert_eq!(chip8.emulate(), Err(Chip8Error::StackUnderflow));
+        assert_eq!(chip8.program_counter, 0x204);
+    }
 
-                    // set the index register to the font sprite address of the character contained in the register
-                    0x0029 => {
-                        let character = self.register[((opcode & 0x0F00) >> 8) as usize] as u16;
-                        self.index_register = 5 * character;
+    #[test]
+    fn bulk_memory_access() {
+        let mut chip8 = Chip8::new();
+        assert_eq!(chip8.memory().len(), MEM_SIZE);
+        assert_eq!(chip8.memory()[..FONTSET_SIZE], CHIP8_FONTSET[..]);
 
-                        debug!(
-                            "storing in the index register the address of the character {}",
-                            character
-                        );
-                        debug!("character address: {}", 5 * character);
-                    }
+        chip8.load_memory(&[1, 2, 3]).unwrap();
+        assert_eq!(chip8.memory()[..4], [1, 2, 3, CHIP8_FONTSET[3]]);
 
-                    // fill the registers with data
-                    0x0065 => {
-                        let registers = (opcode & 0x0F00) >> 8;
+        assert!(chip8.load_memory(&[0; MEM_SIZE]).is_ok());
+        assert_eq!(
+            chip8.load_memory(&[0; MEM_SIZE + 1]),
+            Err(Chip8Error::MemoryTooLarge(MEM_SIZE + 1))
+        );
+    }
 
-                        debug!(
-                            "filling registeries from 0 to {:X} of data stored at address {}",
-                            registers, self.index_register
-                        );
+    #[test]
+    fn index_register_near_the_top() {
+        let mut chip8 = Chip8::new();
+        chip8.index_register = 0xFFFF;
+        chip8.register[0] = 2;
+        // I += V0 wraps around
+        load_program(&mut chip8, &[0xF01E]);
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.index_register, 1);
+
+        // drawing or loading from outside the memory fails
+        let mut chip8 = Chip8::new();
+        chip8.index_register = 0xFFFE;
+        chip8.register[15] = 1;
+        chip8.set_draw_cost(3);
+        load_program(&mut chip8, &[0xD005, 0xF565]);
+        assert_eq!(chip8.emulate(), Err(Chip8Error::OutOfBounds(0xFFFE)));
+        // the rejected draw neither changes VF nor takes time
+        assert_eq!(chip8.register[15], 1);
+        assert_eq!(chip8.stall, 0);
+        assert_eq!(chip8.emulate(), Err(Chip8Error::OutOfBounds(0xFFFE)));
+
+        // the last bytes of memory can still be used
+        let mut chip8 = Chip8::new();
+        chip8.index_register = (MEM_SIZE - 2) as u16;
+        chip8.memory[MEM_SIZE - 1] = 0x2A;
+        load_program(&mut chip8, &[0xD002, 0xF165]);
+        chip8.emulate().unwrap();
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[1], 0x2A);
+    }
 
-                        for i in 0..registers {
-                            self.register[i as usize] =
-                                self.memory[(self.index_register + i) as usize];
-                            trace!("new value of {:X}: {}", i, self.register[i as usize]);
-                        }
-                    }
+    #[test]
+    fn accesses_past_the_memory_top() {
+        let mut chip8 = Chip8::new();
+        assert_eq!(chip8.read_mem(MEM_SIZE - 1), Ok(0));
+        assert_eq!(
+            chip8.read_mem(MEM_SIZE),
+            Err(Chip8Error::OutOfBounds(MEM_SIZE))
+        );
+
+        // only the first byte of 2 rows or registers is in memory
+        chip8.index_register = (MEM_SIZE - 1) as u16;
+        load_program(&mut chip8, &[0xD012, 0xF165, 0xF133]);
+        for _ in 0..3 {
+            assert_eq!(chip8.emulate(), Err(Chip8Error::OutOfBounds(MEM_SIZE - 1)));
+        }
+        assert!(chip8.display().iter().flatten().all(|&px| px == 0));
+    }
+
+    #[test]
+    fn program_counter_wraps_around() {
+        let mut chip8 = Chip8::new();
+        // V0 = 0x2A on the last 2 bytes of memory, then V1 = 1 at address 0
+        chip8.memory[MEM_SIZE - 2..].copy_from_slice(&[0x60, 0x2A]);
+        chip8.memory[..2].copy_from_slice(&[0x61, 0x01]);
+        chip8.program_counter = MEM_SIZE - 2;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[0], 0x2A);
+        assert_eq!(chip8.program_counter, 0);
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[1], 1);
+
+        // an opcode on the last byte of memory ends on the first one: 0x6201
+        chip8.memory[MEM_SIZE - 1] = 0x62;
+        chip8.memory[0] = 0x01;
+        chip8.program_counter = MEM_SIZE - 1;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.register[2], 1);
+        assert_eq!(chip8.program_counter, 1);
+
+        // skipping from the end of the memory
+        chip8.memory[MEM_SIZE - 2..].copy_from_slice(&[0x32, 0x01]);
+        chip8.program_counter = MEM_SIZE - 2;
+        chip8.emulate().unwrap();
+        assert_eq!(chip8.program_counter, 2);
+    }
+
+    #[test]
+    fn protected_reserved_memory() {
+        let mut chip8 = Chip8::new();
+        chip8.write_mem(0, 0x2A).unwrap();
+        assert_eq!(chip8.memory[0], 0x2A);
+        assert_eq!(
+            chip8.write_mem(MEM_SIZE, 0x2A),
+            Err(Chip8Error::OutOfBounds(MEM_SIZE))
+        );
+
+        chip8.set_protect_reserved(true);
+        assert_eq!(chip8.write_mem(0, 0), Err(Chip8Error::ProtectedWrite(0)));
+        assert!(chip8.write_mem(PC_START - 1, 0).is_err());
+        assert_eq!(chip8.memory[0], 0x2A);
+        chip8.write_mem(PC_START, 0x2A).unwrap();
+        assert_eq!(chip8.memory[PC_START], 0x2A);
+    }
+
+    #[test]
+    fn custom_fontset() {
+        let mut font = CHIP8_FONTSET;
+        // a "1" made of a single vertical line
+        font[5..10].copy_from_slice(&[0x80; 5]);
+
+        let mut chip8 = Chip8::new();
+        chip8.set_fontset(font);
+        // V0 = 1, I = address of the "1" glyph, draw it at (V1;V1)
+        load_program(&mut chip8, &[0x6001, 0xF029, 0xD115]);
+        for _ in 0..3 {
+            chip8.emulate().unwrap();
+        }
+
+        let drawn = chip8.display_ascii();
+        let glyph: Vec<&str> = drawn.lines().take(6).map(|line| &line[..4]).collect();
+        assert_eq!(glyph, ["#...", "#...", "#...", "#...", "#...", "...."]);
+    }
+
+    // reference behavior of the DXYN drawing opcode, for each quirk setting
+    mod dxyn {
+        use super::*;
+
+        // draw a sprite at (x;y) in a fresh emulator
+        fn draw(quirks: Quirks, x: u8, y: u8, sprite: &[u8]) -> Chip8 {
+            let mut chip8 = Chip8::new();
+            chip8.set_quirks(quirks);
+            draw_again(&mut chip8, x, y, sprite);
+            chip8
+        }
+
+        // draw a sprite at (x;y) over what's already on the screen
+        fn draw_again(chip8: &mut Chip8, x: u8, y: u8, sprite: &[u8]) {
+            chip8.memory[0x300..0x300 + sprite.len()].copy_from_slice(sprite);
+            chip8.register[0] = x;
+            chip8.register[1] = y;
+            chip8.index_register = 0x300;
+            chip8.program_counter = PC_START;
+            load_program(chip8, &[0xD010 | (sprite.len() as u16 & 0xF)]);
+            chip8.emulate().unwrap();
+        }
+
+        // get a w*h area of the screen starting at (x;y), as text
+        fn area(chip8: &Chip8, x: usize, y: usize, w: usize, h: usize) -> Vec<String> {
+            chip8
+                .display_ascii()
+                .lines()
+                .skip(y)
+                .take(h)
+                .map(|line| line[x..x + w].to_string())
+                .collect()
+        }
+
+        // number of set pixels on the screen
+        fn lit(chip8: &Chip8) -> usize {
+            chip8.display_ascii().matches('#').count()
+        }
 
-                    _ => warn!("warning: ran into unknown opcode: {:X}", opcode),
+        fn all_quirks() -> Vec<Quirks> {
+            let mut all = Vec::new();
+            for &draw_zero_height in [false, true].iter() {
+                for &wrap_sprites in [false, true].iter() {
+                    all.push(Quirks {
+                        draw_zero_height,
+                        wrap_sprites,
+                        ..Quirks::default()
+                    });
                 }
             }
+            all
+        }
 
-            _ => warn!("warning: ran into unknown opcode: {:X}", opcode),
+        #[test]
+        fn top_left_corner() {
+            for quirks in all_quirks() {
+                let chip8 = draw(quirks, 0, 0, &[0xF0, 0x90]);
+                assert_eq!(area(&chip8, 0, 0, 5, 3), ["####.", "#..#.", "....."]);
+                assert_eq!(lit(&chip8), 6);
+                assert_eq!(chip8.register[15], 0);
+            }
         }
 
-        Ok(())
+        #[test]
+        fn clipped_by_the_right_edge() {
+            for quirks in all_quirks() {
+                let chip8 = draw(quirks, 60, 0, &[0xFF]);
+                assert_eq!(area(&chip8, 59, 0, 5, 1), [".####"]);
+                if quirks.wrap_sprites {
+                    assert_eq!(area(&chip8, 0, 0, 5, 1), ["####."]);
+                    assert_eq!(lit(&chip8), 8);
+                } else {
+                    assert_eq!(lit(&chip8), 4);
+                }
+                assert_eq!(chip8.register[15], 0);
+            }
+        }
+
+        #[test]
+        fn clipped_by_the_bottom_edge() {
+            for quirks in all_quirks() {
+                let chip8 = draw(quirks, 0, 30, &[0x80; 4]);
+                assert_eq!(area(&chip8, 0, 29, 2, 3), ["..", "#.", "#."]);
+                if quirks.wrap_sprites {
+                    assert_eq!(area(&chip8, 0, 0, 2, 3), ["#.", "#.", ".."]);
+                    assert_eq!(lit(&chip8), 4);
+                } else {
+                    assert_eq!(lit(&chip8), 2);
+                }
+                assert_eq!(chip8.register[15], 0);
+            }
+        }
+
+        #[test]
+        fn clipped_in_the_bottom_right_corner() {
+            for quirks in all_quirks() {
+                let chip8 = draw(quirks, 63, 31, &[0xC0, 0xC0]);
+                assert_eq!(area(&chip8, 62, 30, 2, 2), ["..", ".#"]);
+                if quirks.wrap_sprites {
+                    // the 3 other pixels wrap to the other corners
+                    assert_eq!(area(&chip8, 0, 31, 1, 1), ["#"]);
+                    assert_eq!(area(&chip8, 63, 0, 1, 1), ["#"]);
+                    assert_eq!(area(&chip8, 0, 0, 1, 1), ["#"]);
+                    assert_eq!(lit(&chip8), 4);
+                } else {
+                    assert_eq!(lit(&chip8), 1);
+                }
+                assert_eq!(chip8.register[15], 0);
+            }
+        }
+
+        #[test]
+        fn start_wraps_around_the_screen() {
+            // only the starting position wraps without the quirk
+            for quirks in all_quirks() {
+                let chip8 = draw(quirks, 64 + 5, 32 + 3, &[0x80]);
+                assert_eq!(area(&chip8, 4, 3, 3, 1), [".#."]);
+                assert_eq!(lit(&chip8), 1);
+            }
+        }
+
+        #[test]
+        fn wrapped_in_high_resolution() {
+            let quirks = Quirks {
+                wrap_sprites: true,
+                ..Quirks::default()
+            };
+            let mut chip8 = Chip8::new();
+            chip8.set_quirks(quirks);
+            chip8.high_res = true;
+            chip8.memory[0x300..0x304].copy_from_slice(&[0xFF; 4]);
+            chip8.register[0] = 120;
+            chip8.register[1] = 63;
+            chip8.index_register = 0x300;
+            load_program(&mut chip8, &[0xD010]);
+            chip8.emulate().unwrap();
+
+            // the two set rows of the 16x16 sprite end on the left edge, and the
+            // second one on the top row
+            assert_eq!(area(&chip8, 118, 63, 10, 1), ["..########"]);
+            assert_eq!(area(&chip8, 0, 63, 10, 1), ["########.."]);
+            assert_eq!(area(&chip8, 118, 0, 10, 1), ["..########"]);
+            assert_eq!(area(&chip8, 0, 0, 10, 1), ["########.."]);
+            assert_eq!(lit(&chip8), 32);
+        }
+
+        #[test]
+        fn collision_erases_and_sets_vf() {
+            for quirks in all_quirks() {
+                let mut chip8 = draw(quirks, 5, 5, &[0x80]);
+                draw_again(&mut chip8, 5, 5, &[0xC0]);
+                assert_eq!(area(&chip8, 5, 5, 2, 1), [".#"]);
+                assert_eq!(chip8.register[15], 1);
+
+                // VF is cleared again by a draw without collision
+                draw_again(&mut chip8, 20, 20, &[0x80]);
+                assert_eq!(chip8.register[15], 0);
+            }
+        }
+
+        #[test]
+        fn no_collision_next_to_set_pixels() {
+            for quirks in all_quirks() {
+                let mut chip8 = draw(quirks, 5, 5, &[0x80]);
+                draw_again(&mut chip8, 5, 5, &[0x40]);
+                assert_eq!(area(&chip8, 5, 5, 2, 1), ["##"]);
+                assert_eq!(chip8.register[15], 0);
+            }
+        }
+
+        #[test]
+        fn zero_height_near_the_bottom() {
+            // with the quirk, 16 rows are drawn from the sprite and clipped by the bottom
+            // edge, unless they wrap to the top
+            for quirks in all_quirks() {
+                let mut chip8 = Chip8::new();
+                chip8.set_quirks(quirks);
+                chip8.memory[0x300..0x310].copy_from_slice(&[0x80; 16]);
+                chip8.register[0] = 60;
+                chip8.register[1] = 20;
+                chip8.index_register = 0x300;
+                load_program(&mut chip8, &[0xD010]);
+                chip8.emulate().unwrap();
+
+                let expected = match (quirks.draw_zero_height, quirks.wrap_sprites) {
+                    (false, _) => 0,
+                    (true, false) => 12,
+                    (true, true) => 16,
+                };
+                assert_eq!(lit(&chip8), expected);
+                assert_eq!(chip8.register[15], 0);
+            }
+        }
     }
 }
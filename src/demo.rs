@@ -0,0 +1,416 @@
+// recording of the keys pressed during a run, to play it again identically.
+// A demo is a text file:
+//   rust_chip8 demo <version>
+//   rom <hash of the program>
+//   quirks <quirks>
+//   speed <instructions per frame>
+//   seed <seed of the random numbers>
+//   draw_cost <cycles per draw>
+//   ignore_unknown <1 if the unknown opcodes are skipped, 0 otherwise>
+//   strict <1 if misbehaving programs get an error, 0 otherwise>
+//   protect_reserved <1 if the reserved memory is protected, 0 otherwise>
+//   font <bytes of the font>
+//   rpl <bytes of the RPL user flags>
+//   freeze <value of each frozen register from V0 to VF, - if not frozen>
+//   <cycle> <pressed keys>
+//   ...
+// with one line per change of the pressed keys. Numbers are hexadecimal,
+// the pressed keys having one bit per key and the quirks one bit per quirk
+// (see QUIRK_BITS), and bytes are written as 2 hexadecimal digits each
+
+use crate::chip8::{Chip8, Quirks, FONTSET_SIZE, REGISTER_NUM, RPL_SIZE};
+use std::fs::{read_to_string, write};
+
+// version of the file format, bumped on any change
+const VERSION: u32 = 5;
+
+// bit of each quirk in a demo, from the least significant one
+const QUIRK_BITS: [fn(&mut Quirks) -> &mut bool; 6] = [
+    |quirks| &mut quirks.draw_zero_height,
+    |quirks| &mut quirks.shift_uses_vy,
+    |quirks| &mut quirks.increment_index_on_load_store,
+    |quirks| &mut quirks.wrap_sprites,
+    |quirks| &mut quirks.vf_reset_on_logic,
+    |quirks| &mut quirks.keep_display_on_resolution_switch,
+];
+
+/// the inputs of a run, bound to a program and its configuration
+#[derive(Debug, PartialEq)]
+pub struct Demo {
+    /// hash of the program, as given by Chip8::rom_hash()
+    pub rom_hash: u64,
+    pub quirks: Quirks,
+    /// instructions run per frame, as the timers tick once per frame
+    pub speed: u32,
+    /// seed of the random numbers, as given by Chip8::seed()
+    pub seed: u64,
+    /// cycles taken by a draw, as given by Chip8::draw_cost()
+    pub draw_cost: u32,
+    /// whether the unknown opcodes are skipped instead of stopping the program
+    pub ignore_unknown: bool,
+    /// whether misbehaving programs get an error, as given by Chip8::strict()
+    pub strict: bool,
+    /// whether the reserved memory is protected, as given by Chip8::protect_reserved()
+    pub protect_reserved: bool,
+    /// glyphs used by FX29, as given by Chip8::fontset()
+    pub font: [u8; FONTSET_SIZE],
+    /// RPL user flags read by FX85 at the start, as given by Chip8::rpl(). They are
+    /// saved by the program itself, so they are restored with set_rpl() rather than checked
+    pub rpl: [u8; RPL_SIZE],
+    /// values the registers are locked to, as given by Chip8::frozen_registers()
+    pub frozen_registers: [Option<u8>; REGISTER_NUM],
+    /// the cycles at which the pressed keys change, with the keys pressed from then on
+    pub inputs: Vec<(u64, u16)>,
+}
+
+impl Demo {
+    /// start a demo without any input, for the program loaded in an emulator
+    /// and its configuration
+    pub fn new(chip8: &Chip8, speed: u32) -> Self {
+        Demo {
+            rom_hash: chip8.rom_hash(),
+            quirks: chip8.quirks(),
+            speed,
+            seed: chip8.seed(),
+            draw_cost: chip8.draw_cost(),
+            ignore_unknown: chip8.ignore_unknown(),
+            strict: chip8.strict(),
+            protect_reserved: chip8.protect_reserved(),
+            font: chip8.fontset(),
+            rpl: chip8.rpl(),
+            frozen_registers: chip8.frozen_registers(),
+            inputs: Vec::new(),
+        }
+    }
+
+    /// remember the keys pressed at a cycle, if they changed
+    pub fn record(&mut self, cycle: u64, keys: u16) {
+        if self.keys_at(cycle) == keys {
+            return;
+        }
+
+        // the keys changed again before the cycle got run
+        if self.inputs.last().is_some_and(|&(last, _)| last == cycle) {
+            self.inputs.pop();
+        }
+        self.inputs.push((cycle, keys));
+    }
+
+    /// get the keys pressed at a cycle
+    pub fn keys_at(&self, cycle: u64) -> u16 {
+        match self
+            .inputs
+            .binary_search_by_key(&cycle, |&(input, _)| input)
+        {
+            Ok(i) => self.inputs[i].1,
+            Err(0) => 0,
+            Err(i) => self.inputs[i - 1].1,
+        }
+    }
+
+    /// refuse to play the demo on another program or configuration
+    pub fn check(&self, chip8: &Chip8, speed: u32) -> Result<(), String> {
+        let rom_hash = chip8.rom_hash();
+        let quirks = chip8.quirks();
+        if rom_hash != self.rom_hash {
+            return Err(format!(
+                "the demo was recorded with another program (hash {:016X} instead of {:016X})",
+                self.rom_hash, rom_hash
+            ));
+        }
+
+        if quirks != self.quirks {
+            return Err(format!(
+                "the demo was recorded with other quirks ({:?} instead of {:?})",
+                self.quirks, quirks
+            ));
+        }
+
+        if speed != self.speed {
+            return Err(format!(
+                "the demo was recorded at another speed ({} instead of {})",
+                self.speed, speed
+            ));
+        }
+
+        if chip8.seed() != self.seed {
+            return Err(format!(
+                "the demo was recorded with another seed ({} instead of {})",
+                self.seed,
+                chip8.seed()
+            ));
+        }
+
+        if chip8.draw_cost() != self.draw_cost {
+            return Err(format!(
+                "the demo was recorded with another draw cost ({} instead of {})",
+                self.draw_cost,
+                chip8.draw_cost()
+            ));
+        }
+
+        if chip8.ignore_unknown() != self.ignore_unknown {
+            return Err(format!(
+                "the demo was recorded {} the unknown opcodes",
+                if self.ignore_unknown {
+                    "ignoring"
+                } else {
+                    "stopping on"
+                }
+            ));
+        }
+
+        if chip8.strict() != self.strict {
+            return Err(format!(
+                "the demo was recorded {} strict mode",
+                if self.strict { "in" } else { "out of" }
+            ));
+        }
+
+        if chip8.protect_reserved() != self.protect_reserved {
+            return Err(format!(
+                "the demo was recorded {} the reserved memory protected",
+                if self.protect_reserved {
+                    "with"
+                } else {
+                    "without"
+                }
+            ));
+        }
+
+        if chip8.fontset() != self.font {
+            return Err("the demo was recorded with another font".to_string());
+        }
+
+        if chip8.frozen_registers() != self.frozen_registers {
+            return Err(format!(
+                "the demo was recorded with other frozen registers ({} instead of {})",
+                frozen_text(&self.frozen_registers),
+                frozen_text(&chip8.frozen_registers())
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// write the demo in its text format
+    pub fn encode(&self) -> String {
+        let mut quirks = self.quirks;
+        let quirk_bits = QUIRK_BITS.iter().enumerate().fold(0, |bits, (i, quirk)| {
+            bits | (*quirk(&mut quirks) as u32) << i
+        });
+
+        let mut text = format!(
+            "rust_chip8 demo {}\nrom {:016X}\nquirks {:X}\nspeed {:X}\nseed {:X}\ndraw_cost {:X}\nignore_unknown {}\n\
+             strict {}\nprotect_reserved {}\nfont {}\nrpl {}\nfreeze {}\n",
+            VERSION,
+            self.rom_hash,
+            quirk_bits,
+            self.speed,
+            self.seed,
+            self.draw_cost,
+            self.ignore_unknown as u8,
+            self.strict as u8,
+            self.protect_reserved as u8,
+            hex_bytes(&self.font),
+            hex_bytes(&self.rpl),
+            frozen_text(&self.frozen_registers)
+        );
+
+        for (cycle, keys) in self.inputs.iter() {
+            text += &format!("{:X} {:04X}\n", cycle, keys);
+        }
+
+        text
+    }
+
+    /// read a demo from its text format
+    pub fn decode(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines();
+        let mut header = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .ok_or(format!("missing {} !", name.trim()))
+        };
+
+        let version = header("rust_chip8 demo ")?;
+        if version != VERSION.to_string() {
+            return Err(format!("unsupported demo version {} !", version));
+        }
+
+        let rom_hash = header("rom ")?;
+        let rom_hash = u64::from_str_radix(rom_hash, 16).map_err(|e| e.to_string())?;
+
+        let quirk_bits = u32::from_str_radix(header("quirks ")?, 16).map_err(|e| e.to_string())?;
+        let mut quirks = Quirks::default();
+        for (i, quirk) in QUIRK_BITS.iter().enumerate() {
+            *quirk(&mut quirks) = quirk_bits >> i & 1 == 1;
+        }
+
+        let speed = u32::from_str_radix(header("speed ")?, 16).map_err(|e| e.to_string())?;
+        let seed = u64::from_str_radix(header("seed ")?, 16).map_err(|e| e.to_string())?;
+        let draw_cost =
+            u32::from_str_radix(header("draw_cost ")?, 16).map_err(|e| e.to_string())?;
+        let mut flag = |name: &str| match header(name)? {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            other => Err(format!("invalid {} {} !", name.trim(), other)),
+        };
+        let ignore_unknown = flag("ignore_unknown ")?;
+        let strict = flag("strict ")?;
+        let protect_reserved = flag("protect_reserved ")?;
+
+        let mut font = [0; FONTSET_SIZE];
+        parse_hex_bytes(header("font ")?, &mut font)?;
+        let mut rpl = [0; RPL_SIZE];
+        parse_hex_bytes(header("rpl ")?, &mut rpl)?;
+
+        let frozen: Vec<&str> = header("freeze ")?.split_whitespace().collect();
+        if frozen.len() != REGISTER_NUM {
+            return Err(format!("invalid freeze {} !", frozen.join(" ")));
+        }
+        let mut frozen_registers = [None; REGISTER_NUM];
+        for (register, value) in frozen_registers.iter_mut().zip(frozen) {
+            if value != "-" {
+                *register = Some(u8::from_str_radix(value, 16).map_err(|e| e.to_string())?);
+            }
+        }
+
+        let mut demo = Demo {
+            rom_hash,
+            quirks,
+            speed,
+            seed,
+            draw_cost,
+            ignore_unknown,
+            strict,
+            protect_reserved,
+            font,
+            rpl,
+            frozen_registers,
+            inputs: Vec::new(),
+        };
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            let (cycle, keys) = match (fields.next(), fields.next()) {
+                (Some(cycle), Some(keys)) => (cycle, keys),
+                _ => return Err(format!("invalid input line \"{}\" !", line)),
+            };
+
+            demo.inputs.push((
+                u64::from_str_radix(cycle, 16).map_err(|e| e.to_string())?,
+                u16::from_str_radix(keys, 16).map_err(|e| e.to_string())?,
+            ));
+        }
+
+        Ok(demo)
+    }
+
+    /// save the demo to a file
+    pub fn save(&self, file_path: &str) -> Result<(), String> {
+        write(file_path, self.encode()).map_err(|e| e.to_string())
+    }
+
+    /// read a demo from a file
+    pub fn open(file_path: &str) -> Result<Self, String> {
+        Demo::decode(&read_to_string(file_path).map_err(|e| e.to_string())?)
+    }
+}
+
+// write bytes as hexadecimal, 2 digits per byte
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+// read bytes written by hex_bytes(), which must fill the whole buffer
+fn parse_hex_bytes(text: &str, bytes: &mut [u8]) -> Result<(), String> {
+    if text.len() != 2 * bytes.len() || !text.is_ascii() {
+        return Err(format!("invalid bytes {} !", text));
+    }
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// write the value of each frozen register, - for the other ones
+fn frozen_text(frozen_registers: &[Option<u8>]) -> String {
+    frozen_registers
+        .iter()
+        .map(|value| match value {
+            Some(value) => format!("{:02X}", value),
+            None => "-".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_play() {
+        let quirks = Quirks {
+            draw_zero_height: true,
+            vf_reset_on_logic: true,
+            ..Quirks::default()
+        };
+        let mut chip8 = Chip8::new();
+        chip8.load_bytes(&[0x12, 0x00]).unwrap();
+        chip8.set_quirks(quirks);
+        chip8.set_seed(0x2A);
+        chip8.set_draw_cost(3);
+        chip8.freeze_register(0xA, 0x2A);
+        chip8.set_rpl([1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let mut demo = Demo::new(&chip8, 10);
+        demo.record(0, 0);
+        demo.record(10, 0b10);
+        demo.record(10, 0b11);
+        demo.record(11, 0b11);
+        demo.record(20, 0);
+        assert_eq!(demo.inputs, [(10, 0b11), (20, 0)]);
+        assert_eq!(demo.keys_at(5), 0);
+        assert_eq!(demo.keys_at(15), 0b11);
+
+        let decoded = Demo::decode(&demo.encode()).unwrap();
+        assert!(demo.encode().contains("\nquirks 11\n"));
+        assert!(demo
+            .encode()
+            .contains("\nseed 2A\ndraw_cost 3\nignore_unknown 0\n"));
+        assert!(demo
+            .encode()
+            .contains("\nrpl 0102030405060708\nfreeze - - - - - - - - - - 2A - - - - -\n"));
+        assert_eq!(decoded, demo);
+        assert!(decoded.check(&chip8, 10).is_ok());
+        assert!(decoded.check(&chip8, 1).is_err());
+
+        // any other setting changing the run
+        let changes: [fn(&mut Chip8); 9] = [
+            |chip8| chip8.load_bytes(&[0x12, 0x02]).unwrap(),
+            |chip8| chip8.set_quirks(Quirks::default()),
+            |chip8| chip8.set_seed(1),
+            |chip8| chip8.set_draw_cost(1),
+            |chip8| chip8.set_ignore_unknown(true),
+            |chip8| chip8.set_strict(true),
+            |chip8| chip8.set_protect_reserved(true),
+            |chip8| chip8.set_fontset([0xFF; FONTSET_SIZE]),
+            |chip8| chip8.freeze_register(3, 5),
+        ];
+        for change in changes.iter() {
+            let mut other = Chip8::new();
+            other.load_bytes(&[0x12, 0x00]).unwrap();
+            other.set_quirks(quirks);
+            other.set_seed(0x2A);
+            other.set_draw_cost(3);
+            other.freeze_register(0xA, 0x2A);
+            change(&mut other);
+            assert!(decoded.check(&other, 10).is_err());
+        }
+
+        assert!(Demo::decode(&demo.encode().replace("demo 5", "demo 4")).is_err());
+    }
+}
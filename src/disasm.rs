@@ -0,0 +1,219 @@
+// decoding of opcodes into the usual chip8 assembly mnemonics, see
+// http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#3.1
+
+use std::collections::BTreeSet;
+
+/// get the mnemonic of an opcode, e.g. `LD I, 0x2EA`. Opcodes which aren't
+/// instructions are written as data, e.g. `DW 0x5121`
+pub fn disassemble(opcode: u16) -> String {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let nn = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+
+    match (opcode >> 12, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
+        (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+        (0x0, 0x0, 0xF, 0xB) => "SCR".to_string(),
+        (0x0, 0x0, 0xF, 0xC) => "SCL".to_string(),
+        (0x0, 0x0, 0xF, 0xE) => "LOW".to_string(),
+        (0x0, 0x0, 0xF, 0xF) => "HIGH".to_string(),
+        (0x0, 0x0, 0xC, _) => format!("SCD {}", n),
+        (0x0, 0x0, 0xD, _) => format!("SCU {}", n),
+        (0x0, _, _, _) => format!("SYS 0x{:03X}", nnn),
+        (0x1, _, _, _) => format!("JP 0x{:03X}", nnn),
+        (0x2, _, _, _) => format!("CALL 0x{:03X}", nnn),
+        (0x3, _, _, _) => format!("SE V{:X}, 0x{:02X}", x, nn),
+        (0x4, _, _, _) => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+        (0x6, _, _, _) => format!("LD V{:X}, 0x{:02X}", x, nn),
+        (0x7, _, _, _) => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        (0x8, _, _, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x6) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _, _) => format!("LD I, 0x{:03X}", nnn),
+        (0xB, _, _, _) => format!("JP V0, 0x{:03X}", nnn),
+        (0xC, _, _, _) => format!("RND V{:X}, 0x{:02X}", x, nn),
+        (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+        (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+        (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+        (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+        (0xF, _, 0x7, 0x5) => format!("LD R, V{:X}", x),
+        (0xF, _, 0x8, 0x5) => format!("LD V{:X}, R", x),
+        _ => format!("DW 0x{:04X}", opcode),
+    }
+}
+
+/// decode every 2 bytes of memory from `start` to `end` (excluded) as an opcode,
+/// with its address. A trailing odd byte is left out
+pub fn disassemble_range(mem: &[u8], start: usize, end: usize) -> Vec<(usize, u16, String)> {
+    let end = end.min(mem.len());
+
+    (start..end.saturating_sub(1))
+        .step_by(2)
+        .map(|address| {
+            let opcode = (mem[address] as u16) << 8 | mem[address + 1] as u16;
+            (address, opcode, disassemble(opcode))
+        })
+        .collect()
+}
+
+/// write the listing of memory from `start` to `end` (excluded): one line per opcode
+/// with its address, raw bytes and mnemonic, e.g. `202: A2 EA  LD I, 0x2EA`.
+/// The targets of the jumps and calls get a label (e.g. `L204:`), which the jumps
+/// and calls refer to instead of the address
+pub fn listing(mem: &[u8], start: usize, end: usize) -> String {
+    let opcodes = disassemble_range(mem, start, end);
+
+    // first pass: find the jump and call targets which are opcodes of the listing
+    let targets: BTreeSet<usize> = opcodes
+        .iter()
+        .filter(|(_, opcode, _)| matches!(opcode >> 12, 0x1 | 0x2 | 0xB))
+        .map(|(_, opcode, _)| (opcode & 0xFFF) as usize)
+        .filter(|&target| opcodes.iter().any(|(address, _, _)| *address == target))
+        .collect();
+
+    // second pass: write the opcodes, with a label before each target
+    let mut listing = String::new();
+    for (address, opcode, mnemonic) in opcodes {
+        if targets.contains(&address) {
+            listing += &format!("L{:03X}:\n", address);
+        }
+
+        let target = opcode & 0xFFF;
+        let mnemonic =
+            if matches!(opcode >> 12, 0x1 | 0x2 | 0xB) && targets.contains(&(target as usize)) {
+                mnemonic.replace(&format!("0x{:03X}", target), &format!("L{:03X}", target))
+            } else {
+                mnemonic
+            };
+        listing += &format!(
+            "{:03X}: {:02X} {:02X}  {}\n",
+            address,
+            opcode >> 8,
+            opcode & 0xFF,
+            mnemonic
+        );
+    }
+
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_and_flow() {
+        assert_eq!(disassemble(0x00E0), "CLS");
+        assert_eq!(disassemble(0x00EE), "RET");
+        assert_eq!(disassemble(0x00C4), "SCD 4");
+        assert_eq!(disassemble(0x00D4), "SCU 4");
+        assert_eq!(disassemble(0x00FB), "SCR");
+        assert_eq!(disassemble(0x00FC), "SCL");
+        assert_eq!(disassemble(0x00FE), "LOW");
+        assert_eq!(disassemble(0x00FF), "HIGH");
+        assert_eq!(disassemble(0x0230), "SYS 0x230");
+        assert_eq!(disassemble(0x1228), "JP 0x228");
+        assert_eq!(disassemble(0x2ABC), "CALL 0xABC");
+        assert_eq!(disassemble(0xB300), "JP V0, 0x300");
+    }
+
+    #[test]
+    fn skips() {
+        assert_eq!(disassemble(0x332A), "SE V3, 0x2A");
+        assert_eq!(disassemble(0x4F00), "SNE VF, 0x00");
+        assert_eq!(disassemble(0x5120), "SE V1, V2");
+        assert_eq!(disassemble(0x9AB0), "SNE VA, VB");
+        assert_eq!(disassemble(0xE59E), "SKP V5");
+        assert_eq!(disassemble(0xE5A1), "SKNP V5");
+    }
+
+    #[test]
+    fn registers() {
+        assert_eq!(disassemble(0x6A02), "LD VA, 0x02");
+        assert_eq!(disassemble(0x7CFF), "ADD VC, 0xFF");
+        assert_eq!(disassemble(0x8010), "LD V0, V1");
+        assert_eq!(disassemble(0x8011), "OR V0, V1");
+        assert_eq!(disassemble(0x8012), "AND V0, V1");
+        assert_eq!(disassemble(0x8013), "XOR V0, V1");
+        assert_eq!(disassemble(0x8014), "ADD V0, V1");
+        assert_eq!(disassemble(0x8015), "SUB V0, V1");
+        assert_eq!(disassemble(0x8016), "SHR V0, V1");
+        assert_eq!(disassemble(0x8017), "SUBN V0, V1");
+        assert_eq!(disassemble(0x801E), "SHL V0, V1");
+        assert_eq!(disassemble(0xC10F), "RND V1, 0x0F");
+    }
+
+    #[test]
+    fn memory_and_display() {
+        assert_eq!(disassemble(0xA2EA), "LD I, 0x2EA");
+        assert_eq!(disassemble(0xD015), "DRW V0, V1, 5");
+        assert_eq!(disassemble(0xF107), "LD V1, DT");
+        assert_eq!(disassemble(0xF10A), "LD V1, K");
+        assert_eq!(disassemble(0xF115), "LD DT, V1");
+        assert_eq!(disassemble(0xF118), "LD ST, V1");
+        assert_eq!(disassemble(0xF11E), "ADD I, V1");
+        assert_eq!(disassemble(0xF129), "LD F, V1");
+        assert_eq!(disassemble(0xF133), "LD B, V1");
+        assert_eq!(disassemble(0xF155), "LD [I], V1");
+        assert_eq!(disassemble(0xF165), "LD V1, [I]");
+        assert_eq!(disassemble(0xF775), "LD R, V7");
+        assert_eq!(disassemble(0xF785), "LD V7, R");
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(disassemble(0x5121), "DW 0x5121");
+        assert_eq!(disassemble(0x8018), "DW 0x8018");
+        assert_eq!(disassemble(0xE000), "DW 0xE000");
+        assert_eq!(disassemble(0xF0FF), "DW 0xF0FF");
+    }
+
+    #[test]
+    fn range() {
+        let mem = [0x00, 0xE0, 0x12, 0x00, 0xFF];
+        assert_eq!(
+            disassemble_range(&mem, 0, mem.len()),
+            [
+                (0, 0x00E0, "CLS".to_string()),
+                (2, 0x1200, "JP 0x200".to_string())
+            ]
+        );
+        assert_eq!(disassemble_range(&mem, 2, 100).len(), 1);
+        assert!(disassemble_range(&mem, 4, 5).is_empty());
+    }
+
+    #[test]
+    fn labelled_listing() {
+        // call 0x006, loop, jump outside of the listing, return
+        let mem = [0x20, 0x06, 0x10, 0x02, 0x13, 0x00, 0x00, 0xEE];
+        assert_eq!(
+            listing(&mem, 0, mem.len()),
+            "\
+000: 20 06  CALL L006
+L002:
+002: 10 02  JP L002
+004: 13 00  JP 0x300
+L006:
+006: 00 EE  RET
+"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+// bindings between the keyboard and the 16 keys of the chip8 keypad, which is laid out as
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+
+use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
+
+/// the chip8 key pressed by each keyboard key
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    keys: HashMap<Keycode, u8>,
+}
+
+impl Keymap {
+    /// each key labelled with a hex digit, on the main keyboard or on the numpad
+    pub fn numpad() -> Self {
+        let digits = [
+            (Keycode::Num0, Keycode::Kp0),
+            (Keycode::Num1, Keycode::Kp1),
+            (Keycode::Num2, Keycode::Kp2),
+            (Keycode::Num3, Keycode::Kp3),
+            (Keycode::Num4, Keycode::Kp4),
+            (Keycode::Num5, Keycode::Kp5),
+            (Keycode::Num6, Keycode::Kp6),
+            (Keycode::Num7, Keycode::Kp7),
+            (Keycode::Num8, Keycode::Kp8),
+            (Keycode::Num9, Keycode::Kp9),
+            (Keycode::A, Keycode::KpA),
+            (Keycode::B, Keycode::KpB),
+            (Keycode::C, Keycode::KpC),
+            (Keycode::D, Keycode::KpD),
+            (Keycode::E, Keycode::KpE),
+            (Keycode::F, Keycode::KpF),
+        ];
+
+        let mut keys = HashMap::new();
+        for (key, &(main, numpad)) in digits.iter().enumerate() {
+            keys.insert(main, key as u8);
+            keys.insert(numpad, key as u8);
+        }
+
+        Keymap { keys }
+    }
+
+    /// the 1234/QWER/ASDF/ZXCV block, which has the shape of the chip8 keypad
+    pub fn qwerty() -> Self {
+        let layout = [
+            (Keycode::Num1, 0x1),
+            (Keycode::Num2, 0x2),
+            (Keycode::Num3, 0x3),
+            (Keycode::Num4, 0xC),
+            (Keycode::Q, 0x4),
+            (Keycode::W, 0x5),
+            (Keycode::E, 0x6),
+            (Keycode::R, 0xD),
+            (Keycode::A, 0x7),
+            (Keycode::S, 0x8),
+            (Keycode::D, 0x9),
+            (Keycode::F, 0xE),
+            (Keycode::Z, 0xA),
+            (Keycode::X, 0x0),
+            (Keycode::C, 0xB),
+            (Keycode::V, 0xF),
+        ];
+
+        Keymap {
+            keys: layout.iter().cloned().collect(),
+        }
+    }
+
+    /// get a keymap from its name
+    pub fn find(name: &str) -> Option<Self> {
+        match name {
+            "numpad" => Some(Keymap::numpad()),
+            "qwerty" => Some(Keymap::qwerty()),
+            _ => None,
+        }
+    }
+
+    /// get the chip8 key bound to a keyboard key
+    pub fn key(&self, keycode: Keycode) -> Option<u8> {
+        self.keys.get(&keycode).copied()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::numpad()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keymap_lookups() {
+        let numpad = Keymap::default();
+        assert_eq!(numpad.key(Keycode::Num7), Some(7));
+        assert_eq!(numpad.key(Keycode::Kp7), Some(7));
+        assert_eq!(numpad.key(Keycode::F), Some(15));
+        assert_eq!(numpad.key(Keycode::Q), None);
+
+        let qwerty = Keymap::find("qwerty").unwrap();
+        assert_eq!(qwerty.key(Keycode::Num4), Some(0xC));
+        assert_eq!(qwerty.key(Keycode::X), Some(0));
+        assert_eq!(qwerty.key(Keycode::V), Some(0xF));
+        assert_eq!(qwerty.key(Keycode::Num0), None);
+
+        // every chip8 key can be pressed
+        for keymap in [numpad, qwerty].iter() {
+            for key in 0..16 {
+                assert!(keymap.keys.values().any(|&bound| bound == key));
+            }
+        }
+
+        assert!(Keymap::find("azerty").is_none());
+    }
+}
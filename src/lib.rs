@@ -0,0 +1,14 @@
+// the emulator itself, without any frontend: it can be embedded in other programs,
+// and main.rs is only the SDL frontend built on top of it
+
+extern crate flate2;
+extern crate log;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+pub mod bmp;
+pub mod chip8;
+pub mod demo;
+pub mod disasm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
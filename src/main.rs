@@ -4,69 +4,444 @@
 #[macro_use]
 extern crate clap;
 extern crate log;
+extern crate png;
+extern crate rust_chip8;
 extern crate sdl2;
 extern crate simple_logger;
 
-mod chip8;
-use chip8::Chip8;
+mod keymap;
+mod palette;
+mod sidecar;
+use keymap::Keymap;
+use sidecar::Sidecar;
+
+use rust_chip8::chip8::{Chip8, Chip8Error, DebugState};
+use rust_chip8::demo::Demo;
+use rust_chip8::{bmp, chip8, disasm};
 
 use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::time::Duration;
 
-use log::{debug, error, info, trace, Level};
+use log::{debug, error, info, trace, Level, LevelFilter};
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-use sdl2::event::Event;
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
 
+// frequency of the beep, in Hz
+const TONE_FREQUENCY: f32 = 440.;
+
+// highest speed multiplier, from --speed or the +/- keys
+const MAX_SPEED: u32 = 100;
+
+// title of the window, followed by the last quirk toggled if any
+const WINDOW_TITLE: &str = "Rust Chip8 emulator";
+
+// seed of the random numbers of the runs without a window, which must always give
+// the same screenshot
+const SCREENSHOT_SEED: u64 = 1;
+
+// subcommands of the emulator. Without one of them, `run` is assumed
+const SUBCOMMANDS: [&str; 5] = ["run", "verify", "disasm", "capabilities", "help"];
+
 fn main() -> Result<(), String> {
+    // make `run` the default subcommand, so that `rust_chip8 game.ch8` keeps working
+    let mut args: Vec<OsString> = env::args_os().collect();
+
+    // skip the global verbose option, which can come before the subcommand
+    let mut position = 1;
+    while position < args.len() && (args[position] == "-v" || args[position] == "--verbose") {
+        position += 2;
+    }
+
+    let has_subcommand = args.get(position).is_none_or(|arg| {
+        SUBCOMMANDS.iter().any(|&subcommand| arg == subcommand)
+            || ["-h", "--help", "-V", "--version"]
+                .iter()
+                .any(|&flag| arg == flag)
+    });
+    if !has_subcommand {
+        args.insert(position.min(args.len()), OsString::from("run"));
+    }
+
+    let input_arg = Arg::with_name("input")
+        .help("the .ch8 file to load")
+        .required(true)
+        .value_name("CH8 FILE")
+        .index(1);
+
     let matches = App::new("Rust Chip8 emulator")
         .version("1.0")
         .author("Esteban \"truelossless\" Gressard")
         .about("YeT aNoThEr ChIp8 eMuLaToR wRiTtEn In RuSt")
-        .arg(
-            Arg::with_name("input")
-                .help("the .ch8 file to load")
-                .required(true)
-                .value_name("CH8 FILE")
-                .index(1),
-        )
+        .setting(AppSettings::SubcommandRequiredElseHelp)
         .arg(
             Arg::with_name("verbose")
                 .help("how verbose should the emulator be")
                 .short("v")
                 .long("verbose")
                 .value_name("LEVEL")
-                .possible_values(&["trace", "debug", "info", "warn", "error"]),
+                .possible_values(&["trace", "debug", "info", "warn", "error"])
+                .global(true),
         )
-        .arg(
-            Arg::with_name("pixel")
-            .help("how big should a chip8 pixel be (default: 10)")
-            .short("p")
-            .long("pixel-size")
-            .value_name("SIZE")
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("run a program (default)")
+                .arg(input_arg.clone())
+                .arg(
+                    Arg::with_name("pixel")
+                        .help("how big should a chip8 pixel be (default: 10)")
+                        .short("p")
+                        .long("pixel-size")
+                        .value_name("SIZE"),
+                )
+                .arg(
+                    Arg::with_name("pixel-shape")
+                        .help("draw the chip8 pixels as squares or circles (default: square)")
+                        .long("pixel-shape")
+                        .value_name("SHAPE")
+                        .possible_values(&["square", "circle"]),
+                )
+                .arg(
+                    Arg::with_name("speed")
+                        .help("emulation speed multiplier, from 1 to 100 (+ and - change it while running)")
+                        .short("s")
+                        .long("speed")
+                        .value_name("MULTIPLIER"),
+                )
+                .arg(
+                    Arg::with_name("profile")
+                        .help("time each opcode family and print a summary at exit")
+                        .long("profile"),
+                )
+                .arg(
+                    Arg::with_name("freeze")
+                        .help("lock a register to a value, e.g. V3=05 (hexadecimal)")
+                        .long("freeze")
+                        .value_name("VX=NN")
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("highlight-draws")
+                        .help("show the pixels turned on by the last drawing operation in another color")
+                        .long("highlight-draws"),
+                )
+                .arg(
+                    Arg::with_name("palette")
+                        .help("colors of the screen: classic, high-contrast, colorblind, amber, green or mono (default: classic)")
+                        .long("palette")
+                        .value_name("NAME"),
+                )
+                .arg(
+                    Arg::with_name("fg")
+                        .help("color of the pixels in hex, e.g. 00FF00, overriding the palette")
+                        .long("fg")
+                        .value_name("RRGGBB"),
+                )
+                .arg(
+                    Arg::with_name("bg")
+                        .help("color of the background in hex, e.g. 101020, overriding the palette")
+                        .long("bg")
+                        .value_name("RRGGBB"),
+                )
+                .arg(
+                    Arg::with_name("quirks")
+                        .help("behave like another interpreter: chip8 (COSMAC VIP) or schip (SUPER-CHIP 1.1)")
+                        .long("quirks")
+                        .value_name("PRESET"),
+                )
+                .arg(
+                    Arg::with_name("wrap-sprites")
+                        .help("wrap the sprites going past an edge of the screen to the other side, instead of clipping them")
+                        .long("wrap-sprites"),
+                )
+                .arg(
+                    Arg::with_name("keep-display-on-resolution-switch")
+                        .help("keep the screen when a SUPER-CHIP program switches resolution, instead of clearing it")
+                        .long("keep-display-on-resolution-switch"),
+                )
+                .arg(
+                    Arg::with_name("keymap")
+                        .help("keyboard layout: numpad (hex digits) or qwerty (1234/QWER/ASDF/ZXCV) (default: numpad)")
+                        .long("keymap")
+                        .value_name("NAME"),
+                )
+                .arg(
+                    Arg::with_name("export-bmp")
+                        .help("save the screen as a monochrome BMP when pressing F12 and at exit")
+                        .long("export-bmp")
+                        .value_name("PATH"),
+                )
+                .arg(
+                    Arg::with_name("border")
+                        .help("width of the dark border around the screen, in chip8 pixels (default: 0)")
+                        .long("border")
+                        .value_name("SIZE"),
+                )
+                .arg(
+                    Arg::with_name("break-on-start")
+                        .help("start paused on the first instruction (P: resume, space: step)")
+                        .long("break-on-start"),
+                )
+                .arg(
+                    Arg::with_name("step")
+                        .help("start paused, and print each instruction stepped through with space and the registers it changed. While paused, F10 steps over a call, F11 steps out of a subroutine and F8 runs to a breakpoint")
+                        .long("step"),
+                )
+                .arg(
+                    Arg::with_name("breakpoint")
+                        .help("stop on an address with F8 in step mode, e.g. 2A4 (hexadecimal)")
+                        .long("breakpoint")
+                        .value_name("ADDRESS")
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("warn-vf-reuse")
+                        .help("warn when VF seems used both as a general register and as a flag (may be wrong)")
+                        .long("warn-vf-reuse"),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .help("treat the program misbehaving as an error (e.g. returning without a subroutine)")
+                        .long("strict"),
+                )
+                .arg(
+                    Arg::with_name("trap-halt")
+                        .help("stop with an error on a jump to itself, and pause on it")
+                        .long("trap-halt"),
+                )
+                .arg(
+                    Arg::with_name("ignore-unknown")
+                        .help("skip the opcodes the emulator can't run with a warning, instead of pausing on them")
+                        .long("ignore-unknown"),
+                )
+                .arg(
+                    Arg::with_name("trace-hash")
+                        .help("print at exit a hash of every instruction run, to compare runs")
+                        .long("trace-hash"),
+                )
+                .arg(
+                    Arg::with_name("record-demo")
+                        .help("save the keys pressed during the run to a demo file")
+                        .long("record-demo")
+                        .value_name("PATH")
+                        .conflicts_with("play-demo"),
+                )
+                .arg(
+                    Arg::with_name("play-demo")
+                        .help("play the keys of a demo file instead of the keyboard ones")
+                        .long("play-demo")
+                        .value_name("PATH"),
+                )
+                .arg(
+                    Arg::with_name("skip-intro")
+                        .help("run the first CYCLES instructions at once, before opening the window")
+                        .long("skip-intro")
+                        .value_name("CYCLES"),
+                )
+                .arg(
+                    Arg::with_name("log-every")
+                        .help("only log one instruction every N instructions (default: 1)")
+                        .long("log-every")
+                        .value_name("N"),
+                )
+                .arg(
+                    Arg::with_name("dump-frames")
+                        .help("write every frame as raw RGB24 pixels to a file or a named pipe")
+                        .long("dump-frames")
+                        .value_name("PATH"),
+                )
+                .arg(
+                    Arg::with_name("protect-reserved")
+                        .help("stop the program writing to the fontset and reserved memory (below 0x200)")
+                        .long("protect-reserved"),
+                )
+                .arg(
+                    Arg::with_name("font")
+                        .help("a file of 80 bytes replacing the hexadecimal font (5 bytes per character)")
+                        .long("font")
+                        .value_name("FILE"),
+                )
+                .arg(
+                    Arg::with_name("draw-cost")
+                        .help("number of cycles a draw takes, to slow down the flicker of some programs (default: 1)")
+                        .long("draw-cost")
+                        .value_name("CYCLES"),
+                )
+                .arg(
+                    Arg::with_name("trace-branches")
+                        .help("log every conditional skip, with the compared values and the outcome")
+                        .long("trace-branches"),
+                )
+                .arg(
+                    Arg::with_name("screenshot-at")
+                        .help("run without a window for CYCLE instructions, save a screenshot and exit")
+                        .long("screenshot-at")
+                        .value_name("CYCLE")
+                        .requires("screenshot"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .help("seed of the random numbers (CXNN), to make a run reproducible (default: the clock, or a fixed one with --screenshot-at)")
+                        .long("seed")
+                        .value_name("SEED"),
+                )
+                .arg(
+                    Arg::with_name("screenshot")
+                        .help("where to save the PNG screenshot taken with --screenshot-at")
+                        .long("screenshot")
+                        .value_name("PATH")
+                        .requires("screenshot-at"),
+                ),
         )
-        .arg(
-            Arg::with_name("speed")
-            .help("emulation speed multiplier")
-            .short("s")
-            .long("speed")
-            .value_name("MULTIPLIER")
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("list the opcodes of a program the emulator can't run")
+                .arg(input_arg.clone())
+                .arg(
+                    Arg::with_name("compat-report")
+                        .help("also run the program for at most CYCLES instructions, and summarize what went wrong")
+                        .long("compat-report")
+                        .value_name("CYCLES"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("print a program as assembly")
+                .arg(input_arg)
+                .arg(
+                    Arg::with_name("disasm-out")
+                        .help("write the listing to a file instead, with the raw bytes and labels at the jump and call targets")
+                        .long("disasm-out")
+                        .value_name("PATH"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("capabilities").about("list the opcodes the emulator can run"),
         )
-        
-        .get_matches();
+        .get_matches_from(args);
+
+    let (subcommand, sub_matches) = matches.subcommand();
+    let sub_matches = sub_matches.unwrap_or(&matches);
+
+    let log_level = match sub_matches.value_of("verbose").unwrap_or("info") {
+        "trace" => Level::Trace,
+        "debug" => Level::Debug,
+        "warn" => Level::Warn,
+        "error" => Level::Error,
+        _ => Level::Info,
+    };
+    simple_logger::init_with_level(log_level).unwrap();
+
+    match subcommand {
+        "verify" => verify(sub_matches),
+        "disasm" => {
+            disassemble(sub_matches);
+            Ok(())
+        }
+        "capabilities" => {
+            capabilities();
+            Ok(())
+        }
+        _ => run(sub_matches, log_level),
+    }
+}
+
+// list the opcodes the emulator can run
+fn capabilities() {
+    for (pattern, description) in chip8::OPCODES.iter() {
+        println!("{}: {}", pattern, description);
+    }
+}
+
+// list the opcodes of a program the emulator can't run
+fn verify(matches: &ArgMatches) -> Result<(), String> {
+    let mut chip8 = Chip8::new();
+    load_rom(matches.value_of("input").unwrap(), &mut chip8);
+
+    let unimplemented = chip8.unimplemented_opcodes();
+    for (address, opcode) in unimplemented.iter() {
+        println!("{:03X}: {:04X}", address, opcode);
+    }
+    println!(
+        "{} unimplemented opcode(s) found (data may be decoded as opcodes too)",
+        unimplemented.len()
+    );
+
+    if let Some(cycles) = matches.value_of("compat-report") {
+        let cycles = value_t!(matches, "compat-report", usize).unwrap_or_else(|_| {
+            error!("invalid number of cycles: {} !", cycles);
+            std::process::exit(1);
+        });
+
+        // the report already sums up the warnings of each instruction
+        let log_level = log::max_level();
+        log::set_max_level(LevelFilter::Error);
+        let report = chip8.compat_report(cycles);
+        log::set_max_level(log_level);
+
+        println!();
+        println!("{} instruction(s) run", report.cycles);
+        for (opcode, count) in report.unknown_opcodes.iter() {
+            println!("unknown opcode {:04X} run {} time(s)", opcode, count);
+        }
+        for (err, count) in report.errors.iter() {
+            println!("{} ({} time(s))", err, count);
+        }
+        println!(
+            "{} write(s) of the program to its own code",
+            report.self_modifying_writes
+        );
+        match report.halted_at {
+            Some(address) => println!("halted on a jump to itself at {:03X}", address),
+            None if report.waiting_for_key => println!("stopped waiting for a key"),
+            None => println!("still running"),
+        }
+    }
+
+    Ok(())
+}
+
+// print a program as assembly
+fn disassemble(matches: &ArgMatches) {
+    let mut chip8 = Chip8::new();
+    load_rom(matches.value_of("input").unwrap(), &mut chip8);
 
-    match matches.value_of("verbose").unwrap_or("info") {
-        "trace" => simple_logger::init_with_level(Level::Trace).unwrap(),
-        "debug" => simple_logger::init_with_level(Level::Debug).unwrap(),
-        "warn" => simple_logger::init_with_level(Level::Warn).unwrap(),
-        "error" => simple_logger::init_with_level(Level::Error).unwrap(),
-        "info" | _ => simple_logger::init_with_level(Level::Info).unwrap(),
+    let start = chip8::PC_START;
+    let end = start + chip8.rom_len();
+
+    if let Some(listing_path) = matches.value_of("disasm-out") {
+        let listing = disasm::listing(chip8.memory(), start, end);
+        if let Err(e) = std::fs::write(listing_path, listing) {
+            error!("unable to write the listing {} !", listing_path);
+            error!("full error: {}", e);
+            std::process::exit(1);
+        }
+        info!("Listing saved to {}", listing_path);
+        return;
     }
 
+    for (address, opcode, mnemonic) in disasm::disassemble_range(chip8.memory(), start, end) {
+        println!("{:03X}: {:04X}  {}", address, opcode, mnemonic);
+    }
+}
+
+// run a program in a window
+fn run(matches: &ArgMatches, log_level: Level) -> Result<(), String> {
+    // only log the emulation of one instruction every log_every instructions,
+    // warnings and errors excepted
+    let log_every = value_t!(matches, "log-every", u64).unwrap_or(1).max(1);
+
     info!("Starting emulator ...");
 
     // enlargment factor between one chip8 pixel and one real pixel
@@ -74,49 +449,492 @@ fn main() -> Result<(), String> {
     let px_size = value_t!(matches, "pixel", u8).unwrap_or(10) as u32;
     trace!("Pixel ratio: {}:1", px_size);
 
-    // speed multiplicator
-    let mut speed = value_t!(matches, "speed", u32).unwrap_or(1);
-    if speed > 100 {
-        speed = 100;
+    // rectangles drawing one chip8 pixel, from its top left corner
+    let circle_pixels = matches.value_of("pixel-shape") == Some("circle");
+    let pixel_shape = pixel_shape(px_size, circle_pixels);
+
+    // overscan border around the screen, in real pixels
+    let border = value_t!(matches, "border", u8).unwrap_or(0) as u32 * px_size;
+
+    let highlight_draws = matches.is_present("highlight-draws");
+
+    // settings recommended for the program, which the command line overrides
+    let sidecar = load_sidecar(&format!("{}.json", matches.value_of("input").unwrap()));
+
+    let palette_name = matches.value_of("palette").unwrap_or("classic");
+    let mut palette = palette::find(palette_name).unwrap_or_else(|| {
+        error!("unknown palette {} !", palette_name);
+        std::process::exit(1);
+    });
+    if !matches.is_present("palette") {
+        if let Some(color) = sidecar.fill_color {
+            palette.foreground = color;
+        }
+        if let Some(color) = sidecar.background_color {
+            palette.background = color;
+        }
+    }
+    let color_arg = |name: &str| {
+        matches.value_of(name).map(|hex| {
+            palette::parse_color(hex).unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(1);
+            })
+        })
+    };
+    if let Some(color) = color_arg("fg") {
+        palette.foreground = color;
     }
+    if let Some(color) = color_arg("bg") {
+        palette.background = color;
+    }
+    let (red, green, blue) = palette.background;
+    let background = Color::RGB(red, green, blue);
+    let (red, green, blue) = palette.foreground;
+    let foreground = Color::RGB(red, green, blue);
+    let (red, green, blue) = palette.highlight;
+    let highlight = Color::RGB(red, green, blue);
+    let bmp_path = matches.value_of("export-bmp");
+
+    let keymap_name = matches.value_of("keymap").unwrap_or("numpad");
+    let keymap = Keymap::find(keymap_name).unwrap_or_else(|| {
+        error!("unknown keymap {} !", keymap_name);
+        std::process::exit(1);
+    });
+
+    // F5 saves the state of the machine next to the program, F9 restores it
+    let state_path = format!("{}.state", matches.value_of("input").unwrap());
+
+    // the RPL user flags of SUPER-CHIP programs (e.g. high scores) are kept next to the program
+    let rpl_path = format!("{}.rpl", matches.value_of("input").unwrap());
+
+    // speed multiplicator: number of instructions run per frame. The frames last
+    // 1/60s whatever the speed, so that the timers stay at 60Hz
+    let mut speed = value_t!(matches, "speed", u32)
+        .unwrap_or_else(|_| sidecar.tickrate.unwrap_or(1))
+        .clamp(1, MAX_SPEED);
 
     // emulator initialization
     let mut chip8 = Chip8::new();
-    
-    let rom_path = matches.value_of("input").unwrap();
-    
-    if let Err(e) = chip8.load(rom_path) {
-        error!("unable to open the file {} !", rom_path);
-        error!("full error: {}", e);
-        std::process::exit(1);
-    } else {
-        info!("Loaded file {}", rom_path);
+
+    if matches.is_present("profile") {
+        chip8.enable_profiling();
+    }
+
+    chip8.set_protect_reserved(matches.is_present("protect-reserved"));
+    chip8.set_trace_branches(matches.is_present("trace-branches"));
+    chip8.set_warn_vf_reuse(matches.is_present("warn-vf-reuse"));
+    chip8.set_strict(matches.is_present("strict"));
+    chip8.set_trap_halt(matches.is_present("trap-halt"));
+    chip8.set_ignore_unknown(matches.is_present("ignore-unknown"));
+    chip8.set_draw_cost(value_t!(matches, "draw-cost", u32).unwrap_or(1));
+
+    let mut quirks = chip8.quirks();
+    sidecar.apply_quirks(&mut quirks);
+    chip8.set_quirks(quirks);
+
+    if let Some(preset) = matches.value_of("quirks") {
+        match chip8::Quirks::preset(preset) {
+            Some(quirks) => chip8.set_quirks(quirks),
+            None => {
+                error!("unknown quirks preset {} !", preset);
+                std::process::exit(1);
+            }
+        }
+    }
+    if matches.is_present("wrap-sprites") {
+        let mut quirks = chip8.quirks();
+        quirks.wrap_sprites = true;
+        chip8.set_quirks(quirks);
+    }
+    if matches.is_present("keep-display-on-resolution-switch") {
+        let mut quirks = chip8.quirks();
+        quirks.keep_display_on_resolution_switch = true;
+        chip8.set_quirks(quirks);
+    }
+
+    if let Some(seed) = matches.value_of("seed") {
+        chip8.set_seed(value_t!(matches, "seed", u64).unwrap_or_else(|_| {
+            error!("invalid seed: {} !", seed);
+            std::process::exit(1);
+        }));
+    } else if matches.is_present("screenshot-at") {
+        chip8.set_seed(SCREENSHOT_SEED);
+    }
+
+    if let Some(font_path) = matches.value_of("font") {
+        if let Err(e) = load_fontset(font_path, &mut chip8) {
+            error!("unable to load the font {} !", font_path);
+            error!("full error: {}", e);
+            std::process::exit(1);
+        } else {
+            info!("Loaded font {}", font_path);
+        }
+    }
+
+    for freeze in matches.values_of("freeze").into_iter().flatten() {
+        match parse_freeze(freeze) {
+            Ok((register, value)) => {
+                chip8.freeze_register(register, value);
+                info!("Register V{:X} frozen to {:X}", register, value);
+            }
+            Err(e) => {
+                error!("invalid register freeze {} !", freeze);
+                error!("full error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    for breakpoint in matches.values_of("breakpoint").into_iter().flatten() {
+        match usize::from_str_radix(breakpoint.trim_start_matches("0x"), 16) {
+            Ok(address) => chip8.set_breakpoint(address),
+            Err(e) => {
+                error!("invalid breakpoint {} !", breakpoint);
+                error!("full error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    load_rom(matches.value_of("input").unwrap(), &mut chip8);
+
+    // flags saved by a previous run, if any
+    let rpl = load_rpl(&rpl_path);
+    chip8.set_rpl(rpl);
+
+    // the keys are either recorded or played, from the start of the program
+    let demo_path = matches.value_of("record-demo");
+    let mut recorded_demo = demo_path.map(|_| Demo::new(&chip8, speed));
+    let played_demo = match matches.value_of("play-demo") {
+        Some(demo_path) => match Demo::open(demo_path).and_then(|demo| {
+            // the random numbers of the recording, unless another seed was asked for
+            if !matches.is_present("seed") {
+                chip8.set_seed(demo.seed);
+            }
+            // and the RPL flags it started with, rather than the saved ones
+            chip8.set_rpl(demo.rpl);
+            demo.check(&chip8, speed).map(|_| demo)
+        }) {
+            Ok(demo) => Some(demo),
+            Err(e) => {
+                error!("unable to play the demo {} !", demo_path);
+                error!("full error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let in_demo = recorded_demo.is_some() || played_demo.is_some();
+
+    // so that a run can be reproduced with --seed
+    info!("Seed: {}", chip8.seed());
+
+    if matches.is_present("screenshot-at") {
+        let cycles = value_t!(matches, "screenshot-at", u64).map_err(|e| e.to_string())?;
+        let png_path = matches.value_of("screenshot").unwrap();
+
+        // no window, no input and a fixed seed: the same program always gives
+        // the same screenshot
+        for cycle in 0..cycles {
+            run_cycle(&mut chip8, cycle, speed).unwrap_or_else(|err| println!("{}", err));
+        }
+
+        save_png(png_path, &chip8.display(), px_size, &palette)?;
+        info!("Screenshot of cycle {} saved to {}", cycles, png_path);
+        if matches.is_present("trace-hash") {
+            info!(
+                "trace hash after {} cycles: {:016X}",
+                cycles,
+                chip8.trace_hash()
+            );
+        }
+        return Ok(());
     }
 
+    // fast-forward through the intro, without rendering nor logging every instruction
+    let skip_intro = value_t!(matches, "skip-intro", u64).unwrap_or(0);
+    if skip_intro > 0 {
+        log::set_max_level(LevelFilter::Warn.min(log_level.to_level_filter()));
+        for cycle in 0..skip_intro {
+            run_cycle(&mut chip8, cycle, speed).unwrap_or_else(|err| println!("{}", err));
+        }
+        log::set_max_level(log_level.to_level_filter());
+        info!("Skipped the first {} cycles", skip_intro);
+    }
+
+    // cycles are counted from the start of the program, for the demos
+    let mut cycle = skip_intro;
+
     // sdl2 initialization
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
+    // resolution of the screen, which the window is resized to
+    let mut resolution = (chip8.width(), chip8.height());
+
     let window = video_subsystem
-        .window("Rust Chip8 emulator", 64 * px_size, 32 * px_size)
+        .window(
+            WINDOW_TITLE,
+            resolution.0 as u32 * px_size + 2 * border,
+            resolution.1 as u32 * px_size + 2 * border,
+        )
         .position_centered()
         .opengl()
         .build()
         .map_err(|e| e.to_string())?;
 
     let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+
+    // the screen, one texel per chip8 pixel, scaled up to the window when copied
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            resolution.0 as u32,
+            resolution.1 as u32,
+        )
+        .map_err(|e| e.to_string())?;
     debug!("SDL successfully initialized.");
 
     let mut event_pump = sdl_context.event_pump()?;
 
+    // a square wave, played while the sound timer counts down.
+    // Small buffers make the beep stop as soon as the timer reaches 0
+    let audio_spec = AudioSpecDesired {
+        freq: Some(44_100),
+        channels: Some(1),
+        samples: Some(512),
+    };
+    let beeper = match sdl_context.audio().and_then(|audio| {
+        audio.open_playback(None, &audio_spec, |spec| SquareWave {
+            phase_step: TONE_FREQUENCY / spec.freq as f32,
+            phase: 0.,
+            volume: 0.25,
+        })
+    }) {
+        Ok(device) => Some(device),
+        Err(e) => {
+            error!("unable to open the audio device, the emulator will be silent !");
+            error!("full error: {}", e);
+            None
+        }
+    };
+
+    // where to send the raw frames, e.g. a named pipe read by a video encoder
+    let mut frame_output = match matches.value_of("dump-frames") {
+        Some(frame_path) => match File::create(frame_path) {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(e) => {
+                error!("unable to create the file {} !", frame_path);
+                error!("full error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // the quirks toggled at runtime are restored on reset
+    let startup_quirks = chip8.quirks();
+
+    // while paused, only the instructions explicitly stepped through are run
+    let step_mode = matches.is_present("step");
+    let mut paused = matches.is_present("break-on-start") || step_mode;
+    if paused {
+        info!("Paused on the first instruction. Press P to resume, space to step.");
+    }
+    if step_mode {
+        info!("F10 steps over a call, F11 steps out of a subroutine, F8 runs to a breakpoint.");
+    }
+
+    // whether the last frame highlighted some pixels
+    let mut highlighted = false;
+
     'running: loop {
-        for event in event_pump.poll_iter() {
+        let mut step = false;
+
+        // the window needs to be rendered again, even if the screen didn't change
+        let mut exposed = false;
+
+        // while the program waits for a key (FX0A), sleep until something
+        // happens instead of polling the events at full speed. The timers
+        // must keep counting down though, the highlighted pixels must fade out
+        // and the dumped frames must keep up with the time
+        let blocking_event = if chip8.is_waiting_for_key()
+            && !chip8.timers_active()
+            && !paused
+            && played_demo.is_none()
+            && !highlighted
+            && frame_output.is_none()
+        {
+            Some(event_pump.wait_event())
+        } else {
+            None
+        };
+
+        for event in blocking_event.into_iter().chain(event_pump.poll_iter()) {
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                Event::Window {
+                    win_event: WindowEvent::Exposed,
+                    ..
+                } => exposed = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    if let Some(bmp_path) = bmp_path {
+                        export_bmp(bmp_path, &chip8);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    paused = !paused;
+                    info!("{}", if paused { "Paused." } else { "Resumed." });
+                }
+                // change the number of instructions run per frame
+                Event::KeyDown {
+                    keycode:
+                        Some(
+                            keycode @ (Keycode::Plus
+                            | Keycode::KpPlus
+                            | Keycode::Equals
+                            | Keycode::Minus
+                            | Keycode::KpMinus),
+                        ),
+                    ..
+                } => {
+                    // a demo only plays back at the speed it was recorded at
+                    if in_demo {
+                        info!("the speed can't change during a demo");
+                        continue;
+                    }
+
+                    speed = match keycode {
+                        Keycode::Minus | Keycode::KpMinus => speed - 1,
+                        _ => speed + 1,
+                    }
+                    .clamp(1, MAX_SPEED);
+                    info!("Speed: {}", speed);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Space),
+                    ..
+                } => step = true,
+                // the debugger commands of the step mode, which run several
+                // instructions at once
+                Event::KeyDown {
+                    keycode: Some(keycode @ (Keycode::F8 | Keycode::F10 | Keycode::F11)),
+                    ..
+                } if step_mode && paused => {
+                    // a demo records every cycle, one at a time
+                    if in_demo {
+                        info!("the debugger can't run during a demo");
+                        continue;
+                    }
+
+                    let before = chip8.debug_state();
+                    let result = match keycode {
+                        Keycode::F10 => chip8.step_over(),
+                        Keycode::F11 => chip8.step_out().map(|_| {
+                            println!("returned to {:03X}", chip8.debug_state().program_counter);
+                        }),
+                        _ => chip8.run_until_break().map(|address| {
+                            println!("stopped on the breakpoint at {:03X}", address);
+                        }),
+                    };
+
+                    match result {
+                        Ok(()) if keycode == Keycode::F10 => print_step(&chip8, &before),
+                        Ok(()) => {}
+                        Err(err) => println!("{}", err),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => save_state(&state_path, &chip8),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => {
+                    // a demo is played from the start of the program only
+                    if in_demo {
+                        info!("a state can't be loaded during a demo");
+                        continue;
+                    }
+
+                    load_state(&state_path, &mut chip8);
+                }
+                // restart the program
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => {
+                    // a demo is played from the start of the program only
+                    if in_demo {
+                        info!("the program can't be reset during a demo");
+                        continue;
+                    }
+
+                    chip8.reset();
+                    chip8.set_quirks(startup_quirks);
+                    canvas
+                        .window_mut()
+                        .set_title(WINDOW_TITLE)
+                        .map_err(|e| e.to_string())?;
+                    cycle = 0;
+                    info!("Reset.");
+                }
+                // toggle the compatibility quirks, to find the ones a program needs
+                Event::KeyDown {
+                    keycode:
+                        Some(
+                            keycode @ (Keycode::F2
+                            | Keycode::F3
+                            | Keycode::F4
+                            | Keycode::F6
+                            | Keycode::F7
+                            | Keycode::Home),
+                        ),
+                    ..
+                } => {
+                    // a demo only plays back with the quirks it was recorded with
+                    if in_demo {
+                        info!("the quirks can't change during a demo");
+                        continue;
+                    }
+
+                    let mut quirks = chip8.quirks();
+                    let (name, quirk) = match keycode {
+                        Keycode::F2 => ("draw_zero_height", &mut quirks.draw_zero_height),
+                        Keycode::F3 => ("shift_uses_vy", &mut quirks.shift_uses_vy),
+                        Keycode::F4 => (
+                            "increment_index_on_load_store",
+                            &mut quirks.increment_index_on_load_store,
+                        ),
+                        Keycode::F6 => ("vf_reset_on_logic", &mut quirks.vf_reset_on_logic),
+                        Keycode::F7 => ("wrap_sprites", &mut quirks.wrap_sprites),
+                        _ => (
+                            "keep_display_on_resolution_switch",
+                            &mut quirks.keep_display_on_resolution_switch,
+                        ),
+                    };
+                    *quirk = !*quirk;
+                    info!("{} quirk: {}", name, quirk);
+                    // there is no text on the screen, the title shows the new state instead
+                    canvas
+                        .window_mut()
+                        .set_title(&format!("{} - {} quirk: {}", WINDOW_TITLE, name, quirk))
+                        .map_err(|e| e.to_string())?;
+                    chip8.set_quirks(quirks);
+                }
                 _ => {}
             }
         }
@@ -132,57 +950,507 @@ fn main() -> Result<(), String> {
         chip8.clear_keys();
 
         // send the key to the emulator
-        for key in keys {
-            match key {
-                Keycode::Num0 | Keycode::Kp0 => chip8.register_key(0),
-                Keycode::Num1 | Keycode::Kp1 => chip8.register_key(1),
-                Keycode::Num2 | Keycode::Kp2 => chip8.register_key(2),
-                Keycode::Num3 | Keycode::Kp3 => chip8.register_key(3),
-                Keycode::Num4 | Keycode::Kp4 => chip8.register_key(4),
-                Keycode::Num5 | Keycode::Kp5 => chip8.register_key(5),
-                Keycode::Num6 | Keycode::Kp6 => chip8.register_key(6),
-                Keycode::Num7 | Keycode::Kp7 => chip8.register_key(7),
-                Keycode::Num8 | Keycode::Kp8 => chip8.register_key(8),
-                Keycode::Num9 | Keycode::Kp9 => chip8.register_key(9),
-                Keycode::A | Keycode::KpA => chip8.register_key(10),
-                Keycode::B | Keycode::KpB => chip8.register_key(11),
-                Keycode::C | Keycode::KpC => chip8.register_key(12),
-                Keycode::D | Keycode::KpD => chip8.register_key(13),
-                Keycode::E | Keycode::KpE => chip8.register_key(14),
-                Keycode::F | Keycode::KpF => chip8.register_key(15),
-                _ => (),
-            }
-        }
-
-        // run one step of the emulation
-        chip8.emulate().unwrap_or_else(|err| println!("{}", err));
-        // clear the screen (not the emulator screen)
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-
-        canvas.set_draw_color(Color::RGB(255, 255, 255));
-
-        // draw again the scene using the display state of the emulator
-        for (i, row) in chip8.display().iter().enumerate() {
-            for (j, &px) in row.iter().enumerate() {
-                if px == 1 {
-                    let px_rect = Rect::new(
-                        i as i32 * px_size as i32,
-                        j as i32 * px_size as i32,
-                        px_size,
-                        px_size,
-                    );
-
-                    canvas.fill_rect(px_rect)?;
+        for key in keys.into_iter().filter_map(|key| keymap.key(key)) {
+            chip8.register_key(key);
+        }
+
+        // run the instructions of this frame, or a single one when stepping
+        // through a paused program
+        let instructions = if paused { step as u32 } else { speed };
+        for _ in 0..instructions {
+            if let Some(demo) = played_demo.as_ref() {
+                chip8.set_keys(demo.keys_at(cycle));
+            }
+            if let Some(demo) = recorded_demo.as_mut() {
+                demo.record(cycle, chip8.keys());
+            }
+
+            if !cycle.is_multiple_of(log_every) {
+                log::set_max_level(LevelFilter::Warn.min(log_level.to_level_filter()));
+            }
+            // state before a stepped instruction, to print what it changed
+            let before = if step_mode && paused {
+                Some(chip8.debug_state())
+            } else {
+                None
+            };
+
+            let result = run_cycle(&mut chip8, cycle, speed);
+            log::set_max_level(log_level.to_level_filter());
+            cycle += 1;
+
+            if let Some(before) = before {
+                print_step(&chip8, &before);
+            }
+
+            if let Err(err) = result {
+                println!("{}", err);
+
+                // show where the program got stuck. The program can't run as
+                // intended past an unknown opcode either
+                let unknown = matches!(err, Chip8Error::UnknownOpcode(_));
+                if matches!(err, Chip8Error::Halt(_)) || unknown {
+                    paused = true;
+                    info!("Paused on the error. Press P to resume, space to step.");
+                    if unknown {
+                        info!("--ignore-unknown skips the unknown opcodes instead.");
+                    }
+                    break;
                 }
             }
         }
 
-        canvas.present();
+        if let Some(beeper) = beeper.as_ref() {
+            if chip8.is_beeping() && !paused {
+                beeper.resume();
+            } else {
+                beeper.pause();
+            }
+        }
+
+        // only render the screen when it changed. The highlighted pixels need one more
+        // frame to go back to the foreground color
+        let display = chip8.display();
+        if chip8.take_dirty() || exposed || highlighted {
+            // clear the screen (not the emulator screen)
+            canvas.set_draw_color(background);
+            canvas.clear();
+
+            // the pixels which just got drawn, if we need to highlight them
+            let drawn = if highlight_draws {
+                Some(chip8.take_drawn())
+            } else {
+                None
+            };
+            highlighted = drawn
+                .as_ref()
+                .is_some_and(|drawn| drawn.iter().flatten().any(|&px| px == 1));
+
+            // the window follows the resolution of the program
+            if (display.len(), display[0].len()) != resolution {
+                resolution = (display.len(), display[0].len());
+                canvas
+                    .window_mut()
+                    .set_size(
+                        resolution.0 as u32 * px_size + 2 * border,
+                        resolution.1 as u32 * px_size + 2 * border,
+                    )
+                    .map_err(|e| e.to_string())?;
+                texture = texture_creator
+                    .create_texture_streaming(
+                        PixelFormatEnum::RGB24,
+                        resolution.0 as u32,
+                        resolution.1 as u32,
+                    )
+                    .map_err(|e| e.to_string())?;
+                info!("Resolution: {}x{}", resolution.0, resolution.1);
+            }
+
+            // draw again the scene using the display state of the emulator
+            if circle_pixels {
+                // a scaled texture can only make squares, so draw each pixel on its own
+                for (x, column) in display.iter().enumerate() {
+                    for (y, &px) in column.iter().enumerate() {
+                        if px == 1 {
+                            if drawn.as_ref().is_some_and(|drawn| drawn[x][y] == 1) {
+                                canvas.set_draw_color(highlight);
+                            } else {
+                                canvas.set_draw_color(foreground);
+                            }
+
+                            let left = (x as u32 * px_size + border) as i32;
+                            let top = (y as u32 * px_size + border) as i32;
+
+                            for rect in pixel_shape.iter() {
+                                canvas.fill_rect(Rect::new(
+                                    left + rect.x(),
+                                    top + rect.y(),
+                                    rect.width(),
+                                    rect.height(),
+                                ))?;
+                            }
+                        }
+                    }
+                }
+            } else {
+                texture.with_lock(None, |texels, pitch| {
+                    fill_texture(texels, pitch, &display, drawn.as_deref(), &palette)
+                })?;
+                canvas.copy(
+                    &texture,
+                    None,
+                    Rect::new(
+                        border as i32,
+                        border as i32,
+                        resolution.0 as u32 * px_size,
+                        resolution.1 as u32 * px_size,
+                    ),
+                )?;
+            }
+
+            canvas.present();
+        }
+
+        if let Some(output) = frame_output.as_mut() {
+            if let Err(e) = output.write_all(&rgb_frame(&display, px_size, &palette)) {
+                error!("unable to write the frame, no more frames will be written !");
+                error!("full error: {}", e);
+                frame_output = None;
+            }
+        }
 
         // achieve 60 fps, as in the chip8 spec
-        std::thread::sleep(Duration::new(0, 1_000_000_000 / (60*speed)));
+        std::thread::sleep(Duration::new(0, 1_000_000_000 / 60));
+    }
+
+    if let Some(bmp_path) = bmp_path {
+        export_bmp(bmp_path, &chip8);
+    }
+
+    if let (Some(demo_path), Some(demo)) = (demo_path, recorded_demo) {
+        match demo.save(demo_path) {
+            Ok(()) => info!("Demo saved to {}", demo_path),
+            Err(e) => {
+                error!("unable to save the demo to {} !", demo_path);
+                error!("full error: {}", e);
+            }
+        }
+    }
+
+    if let Some(profile) = chip8.profile() {
+        info!("time spent per opcode family:");
+        for (family, &(count, total)) in profile.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+
+            info!(
+                "{:X}XXX: {} executions, {} ns total, {} ns average",
+                family,
+                count,
+                total.as_nanos(),
+                total.as_nanos() / count as u128
+            );
+        }
+    }
+
+    if matches.is_present("trace-hash") {
+        info!(
+            "trace hash after {} cycles: {:016X}",
+            cycle,
+            chip8.trace_hash()
+        );
+    }
+
+    // only write the flags of the programs using them. The flags of a played demo
+    // are the ones of the recording, not the player's
+    if chip8.rpl() != rpl && played_demo.is_none() {
+        match std::fs::write(&rpl_path, chip8.rpl()) {
+            Ok(()) => info!("RPL flags saved to {}", rpl_path),
+            Err(e) => {
+                error!("unable to save the RPL flags to {} !", rpl_path);
+                error!("full error: {}", e);
+            }
+        }
     }
 
     Ok(())
 }
+
+// a square wave generator for the beep
+struct SquareWave {
+    // fraction of a period elapsed per sample
+    phase_step: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase < 0.5 {
+                self.volume
+            } else {
+                -self.volume
+            };
+            self.phase = (self.phase + self.phase_step) % 1.;
+        }
+    }
+}
+
+// run the instruction of a cycle. The timers count down once every `speed` cycles,
+// so at 60Hz when running `speed` instructions per frame, and always at the same
+// cycles for a given speed (which demos rely on). Any `speed` cycles in a row hold
+// one tick, so this stays true when the speed changes between two frames
+fn run_cycle(chip8: &mut Chip8, cycle: u64, speed: u32) -> Result<(), Chip8Error> {
+    let result = chip8.step();
+    if (cycle + 1).is_multiple_of(speed as u64) {
+        chip8.tick_timers();
+    }
+    result
+}
+
+// print an instruction stepped through with --step, and the registers it changed
+fn print_step(chip8: &Chip8, before: &DebugState) {
+    let after = chip8.debug_state();
+    let address = before.program_counter;
+    let memory = chip8.memory();
+    let byte = |address: usize| *memory.get(address).unwrap_or(&0) as u16;
+    let opcode = byte(address) << 8 | byte(address + 1);
+
+    let mut changes = Vec::new();
+    for (register, (old, new)) in before
+        .registers
+        .iter()
+        .zip(after.registers.iter())
+        .enumerate()
+    {
+        if old != new {
+            changes.push(format!("V{:X}: {:02X} -> {:02X}", register, old, new));
+        }
+    }
+    if before.index_register != after.index_register {
+        changes.push(format!(
+            "I: {:03X} -> {:03X}",
+            before.index_register, after.index_register
+        ));
+    }
+
+    println!(
+        "{:03X}: {:04X}  {:<16} {}",
+        address,
+        opcode,
+        disasm::disassemble(opcode),
+        changes.join(", ")
+    );
+}
+
+/// render a display (indexed as display[x][y]) as raw pixels in the colors of a palette,
+/// scaled by px_size:
+/// 3 bytes per pixel (red, green, blue), row after row from the top left corner,
+/// without any padding. A frame is (64 * px_size) pixels wide, so the stride is
+/// 64 * px_size * 3 bytes, and (32 * px_size) pixels high; twice as much in both
+/// directions in the SUPER-CHIP high resolution.
+/// e.g. with the default pixel size, it can be encoded with
+/// ffmpeg -f rawvideo -pixel_format rgb24 -video_size 640x320 -framerate 60 -i frames.rgb out.mp4
+fn rgb_frame(display: &[Vec<u8>], px_size: u32, palette: &palette::Palette) -> Vec<u8> {
+    let px_size = px_size as usize;
+    let width = display.len() * px_size;
+    let height = display.first().map_or(0, |column| column.len()) * px_size;
+    let (red, green, blue) = palette.background;
+    let mut frame = [red, green, blue].repeat(width * height);
+
+    for (x, column) in display.iter().enumerate() {
+        for (y, &px) in column.iter().enumerate() {
+            if px == 0 {
+                continue;
+            }
+
+            let (red, green, blue) = palette.foreground;
+            for row in y * px_size..(y + 1) * px_size {
+                let start = (row * width + x * px_size) * 3;
+                for color in frame[start..start + px_size * 3].chunks_mut(3) {
+                    color.copy_from_slice(&[red, green, blue]);
+                }
+            }
+        }
+    }
+
+    frame
+}
+
+// color the texels of an RGB24 texture from a display (indexed as display[x][y]),
+// the pixels which just got drawn being highlighted if given
+fn fill_texture(
+    texels: &mut [u8],
+    pitch: usize,
+    display: &[Vec<u8>],
+    drawn: Option<&[Vec<u8>]>,
+    palette: &palette::Palette,
+) {
+    for (x, column) in display.iter().enumerate() {
+        for (y, &px) in column.iter().enumerate() {
+            let (red, green, blue) = if px == 0 {
+                palette.background
+            } else if drawn.is_some_and(|drawn| drawn[x][y] == 1) {
+                palette.highlight
+            } else {
+                palette.foreground
+            };
+
+            let start = y * pitch + 3 * x;
+            texels[start..start + 3].copy_from_slice(&[red, green, blue]);
+        }
+    }
+}
+
+// get the rectangles drawing one chip8 pixel of px_size real pixels, from its top left corner:
+// either the whole square, or one line per row of real pixels to draw a circle
+fn pixel_shape(px_size: u32, circle: bool) -> Vec<Rect> {
+    if !circle {
+        return vec![Rect::new(0, 0, px_size, px_size)];
+    }
+
+    let radius = px_size as f64 / 2.;
+    (0..px_size)
+        .filter_map(|row| {
+            let dy = row as f64 + 0.5 - radius;
+            let half_width = (radius * radius - dy * dy).sqrt();
+            let left = (radius - half_width).round() as i32;
+            let right = (radius + half_width).round() as i32;
+
+            if right > left {
+                Some(Rect::new(left, row as i32, (right - left) as u32, 1))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// save a display (indexed as display[x][y]) as a PNG image in the colors of a palette,
+// scaled by px_size
+fn save_png(
+    png_path: &str,
+    display: &[Vec<u8>],
+    px_size: u32,
+    palette: &palette::Palette,
+) -> Result<(), String> {
+    let file = File::create(png_path).map_err(|e| e.to_string())?;
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        display.len() as u32 * px_size,
+        display.first().map_or(0, |column| column.len()) as u32 * px_size,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_image_data(&rgb_frame(display, px_size, palette))
+        .map_err(|e| e.to_string())
+}
+
+// load a program in the emulator, exiting if it can't be loaded
+fn load_rom(rom_path: &str, chip8: &mut Chip8) {
+    if let Err(e) = chip8.load(rom_path) {
+        error!("unable to open the file {} !", rom_path);
+        error!("full error: {}", e);
+        std::process::exit(1);
+    } else {
+        info!("Loaded file {}", rom_path);
+    }
+}
+
+// replace the emulator font with the one in a file
+fn load_fontset(font_path: &str, chip8: &mut Chip8) -> Result<(), String> {
+    let font = std::fs::read(font_path).map_err(|e| e.to_string())?;
+    if font.len() != chip8::FONTSET_SIZE {
+        return Err(format!(
+            "expected {} bytes, got {}",
+            chip8::FONTSET_SIZE,
+            font.len()
+        ));
+    }
+
+    let mut fontset = [0; chip8::FONTSET_SIZE];
+    fontset.copy_from_slice(&font);
+    chip8.set_fontset(fontset);
+    Ok(())
+}
+
+// save the screen to a BMP file, reporting the outcome
+fn export_bmp(bmp_path: &str, chip8: &Chip8) {
+    match bmp::save(bmp_path, &chip8.display()) {
+        Ok(()) => info!("Screen saved to {}", bmp_path),
+        Err(e) => {
+            error!("unable to save the screen to {} !", bmp_path);
+            error!("full error: {}", e);
+        }
+    }
+}
+
+// read the RPL user flags saved by a previous run, all 0 if there are none
+// read the settings recommended for a program. Without a sidecar file,
+// nothing is recommended
+fn load_sidecar(sidecar_path: &str) -> Sidecar {
+    match std::fs::read_to_string(sidecar_path) {
+        Ok(text) => match Sidecar::parse(&text) {
+            Ok(sidecar) => {
+                info!("Loaded the settings {}", sidecar_path);
+                if let Some(title) = sidecar.title.as_ref() {
+                    info!("Title: {}", title);
+                }
+                sidecar
+            }
+            Err(e) => {
+                error!("invalid settings {}, they will be ignored !", sidecar_path);
+                error!("full error: {}", e);
+                Sidecar::default()
+            }
+        },
+        Err(_) => Sidecar::default(),
+    }
+}
+
+fn load_rpl(rpl_path: &str) -> [u8; chip8::RPL_SIZE] {
+    let mut rpl = [0; chip8::RPL_SIZE];
+
+    match std::fs::read(rpl_path) {
+        Ok(data) if data.len() == chip8::RPL_SIZE => {
+            rpl.copy_from_slice(&data);
+            info!("Loaded the RPL flags {}", rpl_path);
+        }
+        Ok(_) => error!("invalid RPL flags {}, they will be ignored !", rpl_path),
+        // the program never saved any flag
+        Err(_) => (),
+    }
+
+    rpl
+}
+
+// save the state of the emulator to a file, reporting the outcome
+fn save_state(state_path: &str, chip8: &Chip8) {
+    match std::fs::write(state_path, chip8.save_state()) {
+        Ok(()) => info!("State saved to {}", state_path),
+        Err(e) => {
+            error!("unable to save the state to {} !", state_path);
+            error!("full error: {}", e);
+        }
+    }
+}
+
+// restore the state of the emulator from a file, reporting the outcome
+fn load_state(state_path: &str, chip8: &mut Chip8) {
+    match std::fs::read(state_path)
+        .map_err(|e| e.to_string())
+        .and_then(|state| chip8.load_state(&state).map_err(|e| e.to_string()))
+    {
+        Ok(()) => info!("State loaded from {}", state_path),
+        Err(e) => {
+            error!("unable to load the state from {} !", state_path);
+            error!("full error: {}", e);
+        }
+    }
+}
+
+// parse a register freeze given as VX=NN, both in hexadecimal
+fn parse_freeze(freeze: &str) -> Result<(usize, u8), String> {
+    let mut parts = freeze.splitn(2, '=');
+    let register = parts
+        .next()
+        .unwrap_or("")
+        .trim_start_matches(&['V', 'v'][..]);
+    let value = parts.next().ok_or("expected VX=NN")?;
+
+    let register = usize::from_str_radix(register, 16).map_err(|e| e.to_string())?;
+    if register > 0xF {
+        return Err(format!("there is no register V{:X}", register));
+    }
+    let value = u8::from_str_radix(value, 16).map_err(|e| e.to_string())?;
+
+    Ok((register, value))
+}
@@ -0,0 +1,144 @@
+// colors of the window. The palettes are checked for contrast as in
+// https://www.w3.org/TR/WCAG21/#dfn-contrast-ratio
+
+/// colors used to draw the screen, as (red, green, blue)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub background: (u8, u8, u8),
+    pub foreground: (u8, u8, u8),
+    /// pixels which just got drawn, with --highlight-draws
+    pub highlight: (u8, u8, u8),
+}
+
+/// the palettes which can be picked by name
+pub const PALETTES: &[(&str, Palette)] = &[
+    (
+        "classic",
+        Palette {
+            background: (0, 0, 0),
+            foreground: (255, 255, 255),
+            highlight: (255, 64, 64),
+        },
+    ),
+    // the strongest contrast, with colors far apart in hue as well
+    (
+        "high-contrast",
+        Palette {
+            background: (0, 0, 0),
+            foreground: (255, 255, 0),
+            highlight: (0, 255, 255),
+        },
+    ),
+    // blue and orange from the Okabe-Ito palette, which stay apart with every
+    // common color vision deficiency (unlike red and white for protanopes)
+    (
+        "colorblind",
+        Palette {
+            background: (0, 0, 0),
+            foreground: (230, 159, 0),
+            highlight: (86, 180, 233),
+        },
+    ),
+    // the phosphors of old monochrome monitors
+    (
+        "amber",
+        Palette {
+            background: (24, 12, 0),
+            foreground: (255, 176, 0),
+            highlight: (255, 255, 255),
+        },
+    ),
+    (
+        "green",
+        Palette {
+            background: (0, 16, 0),
+            foreground: (51, 255, 51),
+            highlight: (255, 255, 255),
+        },
+    ),
+    (
+        "mono",
+        Palette {
+            background: (16, 16, 16),
+            foreground: (224, 224, 224),
+            highlight: (255, 64, 64),
+        },
+    ),
+];
+
+/// get a palette from its name
+pub fn find(name: &str) -> Option<Palette> {
+    PALETTES
+        .iter()
+        .find(|(palette_name, _)| *palette_name == name)
+        .map(|&(_, palette)| palette)
+}
+
+/// read a color written in hex as RRGGBB, e.g. `00FF00`. A leading `#` is allowed
+pub fn parse_color(hex: &str) -> Result<(u8, u8, u8), String> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "invalid color {}, expected 6 hex digits like 00FF00 !",
+            hex
+        ));
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).unwrap();
+    Ok((channel(0), channel(2), channel(4)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // relative luminance of a color, between 0 (black) and 1 (white)
+    fn luminance((red, green, blue): (u8, u8, u8)) -> f64 {
+        let linear = |channel: u8| {
+            let channel = channel as f64 / 255.;
+            if channel <= 0.03928 {
+                channel / 12.92
+            } else {
+                ((channel + 0.055) / 1.055).powf(2.4)
+            }
+        };
+
+        0.2126 * linear(red) + 0.7152 * linear(green) + 0.0722 * linear(blue)
+    }
+
+    // contrast ratio between two colors, between 1 (same luminance) and 21 (black and white)
+    fn contrast_ratio(first: (u8, u8, u8), second: (u8, u8, u8)) -> f64 {
+        let (first, second) = (luminance(first), luminance(second));
+        (first.max(second) + 0.05) / (first.min(second) + 0.05)
+    }
+
+    #[test]
+    fn palettes_are_readable() {
+        assert_eq!(contrast_ratio((0, 0, 0), (255, 255, 255)).round(), 21.);
+
+        // 7:1 is the enhanced contrast level of WCAG, for pixels as well as text
+        for (name, palette) in PALETTES.iter() {
+            assert!(
+                contrast_ratio(palette.background, palette.foreground) >= 7.,
+                "{} foreground",
+                name
+            );
+            assert!(
+                contrast_ratio(palette.background, palette.highlight) >= 4.5,
+                "{} highlight",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn colors_from_hex() {
+        assert_eq!(parse_color("00FF00"), Ok((0, 255, 0)));
+        assert_eq!(parse_color("101020"), Ok((16, 16, 32)));
+        assert_eq!(parse_color("#ffb000"), Ok((255, 176, 0)));
+        assert!(parse_color("FFF").is_err());
+        assert!(parse_color("00FF0G").is_err());
+        assert!(parse_color("+0FF00").is_err());
+        assert!(parse_color("").is_err());
+    }
+}
@@ -0,0 +1,301 @@
+// settings recommended for a program, read from a JSON file next to it (<rom>.json).
+// The options are the ones of Octo cartridges, see
+// https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/Manual.md#options
+// Other fields, e.g. the authors of the program, are ignored
+
+use crate::palette;
+use rust_chip8::chip8::Quirks;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// the options of a sidecar file, each one being optional
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sidecar {
+    pub title: Option<String>,
+    /// instructions run per frame
+    pub tickrate: Option<u32>,
+    /// color of the pixels
+    pub fill_color: Option<(u8, u8, u8)>,
+    pub background_color: Option<(u8, u8, u8)>,
+    /// 8XY6 and 8XYE shift VX in place
+    pub shift_quirks: Option<bool>,
+    /// FX55 and FX65 leave I unchanged
+    pub load_store_quirks: Option<bool>,
+    /// 8XY1, 8XY2 and 8XY3 set VF to 0
+    pub logic_quirks: Option<bool>,
+    /// sprites are clipped by the edges of the screen instead of wrapping around
+    pub clip_quirks: Option<bool>,
+}
+
+impl Sidecar {
+    /// read a sidecar file, e.g. `{"tickrate": 15, "shiftQuirks": true}`
+    pub fn parse(text: &str) -> Result<Sidecar, String> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+        };
+        let fields = parser.object()?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.chars.next() {
+            return Err(format!("unexpected {} after the options !", c));
+        }
+
+        let mut sidecar = Sidecar::default();
+        for (name, value) in fields {
+            let invalid = || format!("invalid {} !", name);
+            let flag = || match value {
+                Value::Bool(flag) => Ok(Some(flag)),
+                _ => Err(invalid()),
+            };
+            let color = || match &value {
+                Value::String(hex) => palette::parse_color(hex).map(Some),
+                _ => Err(invalid()),
+            };
+
+            match name.as_str() {
+                "title" => match &value {
+                    Value::String(title) => sidecar.title = Some(title.clone()),
+                    _ => return Err(invalid()),
+                },
+                "tickrate" => match value {
+                    Value::Number(tickrate) if tickrate >= 1. && tickrate <= u32::MAX as f64 => {
+                        sidecar.tickrate = Some(tickrate as u32)
+                    }
+                    _ => return Err(invalid()),
+                },
+                "fillColor" => sidecar.fill_color = color()?,
+                "backgroundColor" => sidecar.background_color = color()?,
+                "shiftQuirks" => sidecar.shift_quirks = flag()?,
+                "loadStoreQuirks" => sidecar.load_store_quirks = flag()?,
+                "logicQuirks" => sidecar.logic_quirks = flag()?,
+                "clipQuirks" => sidecar.clip_quirks = flag()?,
+                _ => {}
+            }
+        }
+
+        Ok(sidecar)
+    }
+
+    /// change the quirks the sidecar has an opinion on
+    pub fn apply_quirks(&self, quirks: &mut Quirks) {
+        if let Some(shift_quirks) = self.shift_quirks {
+            quirks.shift_uses_vy = !shift_quirks;
+        }
+        if let Some(load_store_quirks) = self.load_store_quirks {
+            quirks.increment_index_on_load_store = !load_store_quirks;
+        }
+        if let Some(logic_quirks) = self.logic_quirks {
+            quirks.vf_reset_on_logic = logic_quirks;
+        }
+        if let Some(clip_quirks) = self.clip_quirks {
+            quirks.wrap_sprites = !clip_quirks;
+        }
+    }
+}
+
+// a JSON value, as far as the options are concerned
+enum Value {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    // null, an array or an object
+    Other,
+}
+
+// a minimal JSON reader, enough for the options
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    // consume the given character, after any whitespace
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected {} instead of {} !", expected, c)),
+            None => Err(format!("expected {} at the end !", expected)),
+        }
+    }
+
+    // read the fields of an object, in order
+    fn object(&mut self) -> Result<Vec<(String, Value)>, String> {
+        let mut fields = Vec::new();
+        self.expect('{')?;
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(fields);
+        }
+
+        loop {
+            self.expect('"')?;
+            let name = self.string()?;
+            self.expect(':')?;
+            fields.push((name, self.value()?));
+
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some('}') => return Ok(fields),
+                _ => return Err("unterminated object !".to_string()),
+            }
+        }
+    }
+
+    // read the elements of an array, which are of no use
+    fn array(&mut self) -> Result<(), String> {
+        self.expect('[')?;
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(());
+        }
+
+        loop {
+            self.value()?;
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => return Ok(()),
+                _ => return Err("unterminated array !".to_string()),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('"') => {
+                self.chars.next();
+                Ok(Value::String(self.string()?))
+            }
+            Some('{') => self.object().map(|_| Value::Other),
+            Some('[') => self.array().map(|_| Value::Other),
+            _ => {
+                // a literal or a number, up to the next delimiter
+                let mut word = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c.is_whitespace() || c == ',' || c == '}' || c == ']' {
+                        break;
+                    }
+                    word.push(c);
+                    self.chars.next();
+                }
+
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "null" => Ok(Value::Other),
+                    _ => word
+                        .parse()
+                        .map(Value::Number)
+                        .map_err(|_| format!("invalid value {} !", word)),
+                }
+            }
+        }
+    }
+
+    // read a string, whose opening quote was already consumed
+    fn string(&mut self) -> Result<String, String> {
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let escaped = match self.chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let digits: String = self.chars.by_ref().take(4).collect();
+                            let code = u32::from_str_radix(&digits, 16)
+                                .ok()
+                                .filter(|_| digits.len() == 4)
+                                .ok_or(format!("invalid escape \\u{} !", digits))?;
+                            // surrogate pairs aren't worth decoding for a title
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        Some(c) => c,
+                        None => return Err("unterminated string !".to_string()),
+                    };
+                    string.push(escaped);
+                }
+                Some(c) => string.push(c),
+                None => return Err("unterminated string !".to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn octo_options() {
+        let sidecar = Sidecar::parse(
+            r##"{
+                "title": "Super \"Game\"!",
+                "authors": ["someone", {"name": null}],
+                "tickrate": 15,
+                "fillColor": "#FFCC00",
+                "backgroundColor": "#996600",
+                "shiftQuirks": true,
+                "clipQuirks": false,
+                "maxSize": 3584.5
+            }"##,
+        )
+        .unwrap();
+        assert_eq!(
+            sidecar,
+            Sidecar {
+                title: Some("Super \"Game\"!".to_string()),
+                tickrate: Some(15),
+                fill_color: Some((255, 204, 0)),
+                background_color: Some((153, 102, 0)),
+                shift_quirks: Some(true),
+                load_store_quirks: None,
+                logic_quirks: None,
+                clip_quirks: Some(false),
+            }
+        );
+
+        let mut quirks = Quirks {
+            shift_uses_vy: true,
+            vf_reset_on_logic: true,
+            ..Quirks::default()
+        };
+        sidecar.apply_quirks(&mut quirks);
+        assert_eq!(
+            quirks,
+            Quirks {
+                shift_uses_vy: false,
+                vf_reset_on_logic: true,
+                wrap_sprites: true,
+                ..Quirks::default()
+            }
+        );
+
+        assert_eq!(Sidecar::parse(" {} "), Ok(Sidecar::default()));
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert!(Sidecar::parse("").is_err());
+        assert!(Sidecar::parse("{\"tickrate\": 15").is_err());
+        assert!(Sidecar::parse("{\"tickrate\": \"fast\"}").is_err());
+        assert!(Sidecar::parse("{\"tickrate\": 0}").is_err());
+        assert!(Sidecar::parse("{\"shiftQuirks\": 1}").is_err());
+        assert!(Sidecar::parse("{\"fillColor\": \"yellow\"}").is_err());
+        assert!(Sidecar::parse("{\"title\": \"unterminated}").is_err());
+        assert!(Sidecar::parse("{} {}").is_err());
+    }
+}
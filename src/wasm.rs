@@ -0,0 +1,82 @@
+// bindings of the emulator for javascript, built with the wasm feature. The page drives
+// the loop: e.g. every animation frame, a few step() then tick_timers(), and the screen
+// drawn from display_rgba() on a canvas of width() x height() pixels
+
+use crate::chip8::Chip8;
+use wasm_bindgen::prelude::*;
+
+/// the emulator, as seen from javascript
+#[wasm_bindgen]
+pub struct WasmChip8 {
+    chip8: Chip8,
+}
+
+#[wasm_bindgen]
+impl WasmChip8 {
+    /// a new emulator. The seed of the random numbers should change from one run to
+    /// the other, e.g. Date.now(). It is a javascript number rather than a BigInt,
+    /// and only its integer part is used
+    #[wasm_bindgen(constructor)]
+    pub fn new(seed: f64) -> WasmChip8 {
+        let mut chip8 = Chip8::new();
+        chip8.set_seed(seed as u64);
+        WasmChip8 { chip8 }
+    }
+
+    /// load a program, e.g. the bytes of a fetched .ch8 file
+    pub fn load_bytes(&mut self, rom: &[u8]) -> Result<(), JsValue> {
+        self.chip8
+            .load_bytes(rom)
+            .map_err(|e| JsValue::from(e.to_string()))
+    }
+
+    /// run one instruction
+    pub fn step(&mut self) -> Result<(), JsValue> {
+        self.chip8.step().map_err(|e| JsValue::from(e.to_string()))
+    }
+
+    /// decrement the timers, to call 60 times per second
+    pub fn tick_timers(&mut self) {
+        self.chip8.tick_timers();
+    }
+
+    /// whether a beep should be playing
+    pub fn is_beeping(&self) -> bool {
+        self.chip8.is_beeping()
+    }
+
+    /// press a key of the keypad (0 to F)
+    pub fn register_key(&mut self, key: u8) {
+        if (key as usize) < crate::chip8::KEY_NUM {
+            self.chip8.register_key(key);
+        }
+    }
+
+    /// release all the keys
+    pub fn clear_keys(&mut self) {
+        self.chip8.clear_keys();
+    }
+
+    /// the screen, one byte of 0 or 1 per pixel, row after row from the top left corner
+    pub fn display(&self) -> Vec<u8> {
+        let display = self.chip8.display();
+        (0..self.height())
+            .flat_map(|y| display.iter().map(move |column| column[y]))
+            .collect()
+    }
+
+    /// the screen as RGBA bytes, for an ImageData
+    pub fn display_rgba(&self) -> Vec<u8> {
+        self.chip8.display_rgba()
+    }
+
+    /// width of the screen in pixels, which changes with the resolution
+    pub fn width(&self) -> usize {
+        self.chip8.width()
+    }
+
+    /// height of the screen in pixels, which changes with the resolution
+    pub fn height(&self) -> usize {
+        self.chip8.height()
+    }
+}
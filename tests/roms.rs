@@ -0,0 +1,20 @@
+// run the bundled programs through the library, without any frontend
+
+extern crate rust_chip8;
+
+use rust_chip8::chip8::{Chip8, XPX, YPX};
+
+#[test]
+fn bundled_roms_run() {
+    for rom in ["Airplane.ch8", "Space Invaders.ch8", "Tron.ch8"].iter() {
+        let mut chip8 = Chip8::new();
+        chip8.load(&format!("roms/{}", rom)).unwrap();
+
+        chip8.run_cycles(1000).unwrap();
+
+        // every program draws something within its first instructions
+        let display = chip8.display();
+        assert_eq!((display.len(), display[0].len()), (XPX, YPX));
+        assert!(display.iter().flatten().any(|&px| px == 1), "{}", rom);
+    }
+}